@@ -1,3 +1,6 @@
 fn main() {
-    tauri_build::build()
+    // NOTE: the tauri context is only generated for the app window, a headless build doesn't have tauri
+    if std::env::var_os("CARGO_FEATURE_GUI").is_some() {
+        tauri_build::build()
+    }
 }
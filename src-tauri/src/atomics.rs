@@ -1,9 +1,15 @@
-use crate::{AppState, FileStatus, FileStatusResponse, FileStatusStats, HashMap, Mutex, Path, PathBuf, State, Task, WorkingFile};
-use std::time::SystemTime;
-
-#[tauri::command]
-pub fn solve_duplicates(file_names: Vec<String>, state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
+use crate::execute::{blocked_by_conflict, destination};
+use crate::process_tasks::PreviewCache;
+use crate::{
+    AppState, Conflict, ConflictPolicy, FileKind, FileMetadata, FileStatus, FileStatusResponse, FileStatusStats, FolderOptions, HashMap,
+    HashSet, Output, Path, PathBuf, SortOptions, Symlinks, Task, WorkingFile,
+};
+use icu_collator::{Collator, CollatorOptions, Numeric, Strength};
+use ignore::WalkBuilder;
+use std::cmp::Ordering;
+use std::ffi::OsStr;
+
+pub fn solve_duplicates(file_names: Vec<String>, state: &mut AppState) {
     let mut file_names = file_names;
     state.file_names.append(&mut file_names);
     state.file_names.sort_unstable();
@@ -13,219 +19,440 @@ pub fn solve_duplicates(file_names: Vec<String>, state: &State<'_, Mutex<AppStat
     state.file_names.dedup();
 }
 
-pub fn sort_file_names(state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
-    let sort_choice: &str = &state.sort_choice;
+/// Files are kept as is, folders are walked recursively with the folder options.
+pub fn expand_paths(paths: Vec<String>, folder_options: &FolderOptions) -> Vec<String> {
+    let mut file_names: Vec<String> = Vec::new();
 
-    match sort_choice {
-        "name" => {
-            let mut valid_files: Vec<(String, String)> = state
-                .file_names
-                .iter()
-                .filter_map(|file| {
-                    let filename = Path::new(file).file_name()?.to_string_lossy().to_string();
-                    Some((file.clone(), filename))
-                })
-                .collect();
+    for path in paths {
+        let current_file = PathBuf::from(path.clone());
 
-            valid_files.sort_by_key(|(_, key)| key.clone());
-            state.file_names_sorted = valid_files.into_iter().map(|(path, _)| path).collect();
+        if current_file.is_file() {
+            file_names.push(path);
+        } else if current_file.is_dir() {
+            for entry in folder_walker(&current_file, folder_options).build().filter_map(|e| e.ok()) {
+                file_names.push(entry.path().to_string_lossy().to_string());
+            }
         }
-        "modified" => {
-            let mut valid_files: Vec<(String, SystemTime)> = state
-                .file_names
-                .iter()
-                .filter_map(|file| {
-                    let meta_data = std::fs::metadata(file).ok()?.modified().unwrap_or(SystemTime::UNIX_EPOCH);
-                    Some((file.clone(), meta_data))
-                })
-                .collect();
+    }
+    file_names
+}
 
-            valid_files.sort_by_key(|(_, key)| *key);
-            state.file_names_sorted = valid_files.into_iter().map(|(path, _)| path).collect();
-        }
-        "created" => {
-            let mut valid_files: Vec<(String, SystemTime)> = state
-                .file_names
-                .iter()
-                .filter_map(|file| {
-                    let meta_data = std::fs::metadata(file).ok()?.created().unwrap_or(SystemTime::UNIX_EPOCH);
-                    Some((file.clone(), meta_data))
-                })
-                .collect();
+fn folder_walker(folder: &Path, folder_options: &FolderOptions) -> WalkBuilder {
+    let use_ignore_files = folder_options.use_ignore_files;
+    let skip_symlinks = folder_options.symlinks == Symlinks::Skip;
+
+    let mut walker = WalkBuilder::new(folder);
+    walker
+        .max_depth(folder_options.max_depth)
+        .follow_links(folder_options.symlinks == Symlinks::Follow)
+        .hidden(folder_options.skip_hidden)
+        .ignore(use_ignore_files)
+        .git_ignore(use_ignore_files)
+        .git_exclude(use_ignore_files)
+        .git_global(false)
+        .parents(use_ignore_files)
+        .require_git(false)
+        .filter_entry(move |entry| {
+            // NOTE: the folder that was opened is always walked, even when it is a link itself
+            if entry.depth() == 0 {
+                return true;
+            }
+            // git never tracks its own folder, so honoring ignore files leaves it out too
+            let git_internals = use_ignore_files && entry.file_name() == ".git";
+            !(git_internals || (skip_symlinks && entry.path_is_symlink()))
+        });
+    walker
+}
 
-            valid_files.sort_by_key(|(_, key)| *key);
-            state.file_names_sorted = valid_files.into_iter().map(|(path, _)| path).collect();
-        }
-        "size" => {
-            let mut valid_files: Vec<(String, _)> = state
-                .file_names
-                .iter()
-                .filter_map(|file| {
-                    let meta_data = std::fs::metadata(file).ok()?.len();
-                    Some((file.clone(), meta_data))
-                })
-                .collect();
+/// Reads the metadata of loaded files that don't have it yet and forgets the files that aren't loaded anymore.
+/// Nothing else reads metadata from the disk, so a file is only asked once however often the preview is built.
+pub fn snapshot_metadata(state: &mut AppState) {
+    let loaded: HashSet<&Path> = state.file_names.iter().map(Path::new).collect();
+    state.metadata.retain(|path, _| loaded.contains(path.as_path()));
 
-            valid_files.sort_by_key(|(_, key)| std::cmp::Reverse(*key));
-            state.file_names_sorted = valid_files.into_iter().map(|(path, _)| path).collect();
+    for file_name in &state.file_names {
+        let path = PathBuf::from(file_name);
+        if state.metadata.contains_key(&path) {
+            continue;
         }
-        "type" => {
-            let mut valid_files: Vec<(String, String)> = state
-                .file_names
-                .iter()
-                .filter_map(|file| {
-                    std::fs::metadata(file).ok()?; // File must exist
+        if let Ok(metadata) = path.metadata() {
+            state.metadata.insert(path, FileMetadata::from(&metadata));
+        }
+    }
+}
 
-                    let extension = Path::new(file)
-                        .extension()
-                        .map_or("zz_no_ext".to_string(), |ext| ext.to_string_lossy().to_string());
+/// The active files that changed on disk since their metadata was read, or that are gone.
+pub fn stale_files(state: &AppState) -> Vec<PathBuf> {
+    state
+        .working_files
+        .iter()
+        .filter(|file| file.active)
+        .filter(|file| {
+            let current = file.source.metadata().ok().map(|metadata| FileMetadata::from(&metadata));
+            !matches!((file.metadata, current), (Some(loaded), Some(current)) if loaded.matches(&current))
+        })
+        .map(|file| file.source.clone())
+        .collect()
+}
 
-                    Some((file.clone(), extension))
-                })
-                .collect();
+/// Compares text in the order of the system locale, English when it can't be read.
+fn name_collator(sort_options: &SortOptions) -> Collator {
+    let mut options = CollatorOptions::new();
+    options.strength = Some(if sort_options.case_insensitive { Strength::Secondary } else { Strength::Tertiary });
+    options.numeric = Some(if sort_options.natural { Numeric::On } else { Numeric::Off });
 
-            valid_files.sort_by_key(|(_, key)| key.clone());
-            state.file_names_sorted = valid_files.into_iter().map(|(path, _)| path).collect();
-        }
-        _ => {
-            // for now use the name sorting
-            let mut valid_files: Vec<(String, String)> = state
-                .file_names
-                .iter()
-                .filter_map(|file| {
-                    let filename = Path::new(file).file_name()?.to_string_lossy().to_string();
-                    Some((file.clone(), filename))
-                })
-                .collect();
+    let locale = sys_locale::get_locale().and_then(|locale| locale.parse().ok()).unwrap_or_default();
+    Collator::try_new(&locale, options)
+        .or_else(|_| Collator::try_new(&Default::default(), options))
+        .expect("the root collation is compiled in")
+}
 
-            valid_files.sort_by_key(|(_, key)| key.clone());
-            state.file_names_sorted = valid_files.into_iter().map(|(path, _)| path).collect();
-        }
-    }
+/// Orders two loaded files by one sort key: name, modified, created, size, type, path or parent. Anything else sorts by name.
+fn compare_by(key: &str, a: (&Path, &FileMetadata), b: (&Path, &FileMetadata), collator: &Collator) -> Ordering {
+    let compare_text = |a: Option<&OsStr>, b: Option<&OsStr>| {
+        collator.compare(&a.unwrap_or_default().to_string_lossy(), &b.unwrap_or_default().to_string_lossy())
+    };
 
-    if !state.sort_ascending {
-        state.file_names_sorted.reverse();
+    match key {
+        "modified" => a.1.modified.cmp(&b.1.modified),
+        "created" => a.1.created.cmp(&b.1.created),
+        // NOTE: the biggest files come first, as they always have
+        "size" => b.1.len.cmp(&a.1.len),
+        // files without an extension come after the ones with one
+        "type" => match (a.0.extension(), b.0.extension()) {
+            (Some(a), Some(b)) => collator.compare(&a.to_string_lossy(), &b.to_string_lossy()),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        },
+        "path" => compare_text(Some(a.0.as_os_str()), Some(b.0.as_os_str())),
+        "parent" => compare_text(a.0.parent().map(Path::as_os_str), b.0.parent().map(Path::as_os_str)),
+        _ => compare_text(a.0.file_name(), b.0.file_name()),
     }
 }
 
-pub fn state_update_sort(sort_choice: String, sort_ascending: bool, state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
+/// Sorts the loaded files by the sort choice, then by the `then_by` keys and the full path so the order never depends on chance.
+/// Only the sort choice is reversed when sorting descending. Files without metadata are left out, they can't be loaded.
+pub fn sort_file_names(state: &mut AppState) {
+    let collator = name_collator(&state.sort_options);
+
+    let mut files: Vec<(&String, &Path, &FileMetadata)> = state
+        .file_names
+        .iter()
+        .filter_map(|file| {
+            let path = Path::new(file);
+            path.file_name()?;
+            Some((file, path, state.metadata.get(path)?))
+        })
+        .collect();
+
+    files.sort_by(|a, b| {
+        let (a, b) = ((a.1, a.2), (b.1, b.2));
+        let primary = compare_by(&state.sort_choice, a, b, &collator);
+        let primary = if state.sort_ascending { primary } else { primary.reverse() };
+
+        state
+            .sort_options
+            .then_by
+            .iter()
+            .fold(primary, |ordering, key| ordering.then_with(|| compare_by(key, a, b, &collator)))
+            .then_with(|| compare_by("path", a, b, &collator))
+            .then_with(|| a.0.cmp(b.0))
+    });
+    state.file_names_sorted = files.into_iter().map(|(file, _, _)| file.clone()).collect();
+}
+
+pub fn state_update_sort(sort_choice: String, sort_ascending: bool, state: &mut AppState) {
     state.sort_choice = sort_choice;
     state.sort_ascending = sort_ascending;
     state.selected_filestatus_anchor = None;
     state.selected_filestatuses = None;
 }
 
-#[tauri::command]
-pub fn state_update_tasks(task_list: Vec<Task>, state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
+pub fn state_update_tasks(task_list: Vec<Task>, state: &mut AppState) {
     state.tasks = task_list.clone();
 }
 
-#[tauri::command]
-pub fn state_update_search(search: String, state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
+pub fn state_update_search(search: String, state: &mut AppState) {
     state.search = search;
     state.selected_filestatus_anchor = None;
 }
 
-pub fn state_clear_selected_filestatuses(state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
+pub fn state_clear_selected_filestatuses(state: &mut AppState) {
     state.selected_filestatuses = None;
     state.selected_filestatus_anchor = None;
 }
 
-#[tauri::command]
-pub fn convert_file_names_to_working_files(state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
+pub fn convert_file_names_to_working_files(state: &mut AppState) {
     let mut new_working_files: Vec<WorkingFile> = Vec::with_capacity(state.file_names.len());
     let file_paths = &state.file_names_sorted;
 
     for (index, file_path) in file_paths.iter().enumerate() {
+        let source = PathBuf::from(file_path.clone());
+        let metadata = state.metadata.get(&source).copied();
+        let is_file = metadata.is_some_and(|metadata| metadata.kind == FileKind::File);
+        let is_dir = metadata.is_some_and(|metadata| metadata.kind == FileKind::Directory);
+        let is_legal_file = !file_path.contains(".DS_Store");
+
         let working_file = WorkingFile {
-            source: PathBuf::from(file_path.clone()),
+            source,
             target: PathBuf::new(),
+            is_dir,
             active: true,
             stable_id: index,
+            conflict: Conflict::None,
+            content_hash: None,
+            duplicate_group: None,
+            copy_index: 0,
+            metadata,
         };
 
-        let is_file = working_file.source.is_file();
-        let is_legal_file = !file_path.contains(".DS_Store");
-
-        if is_file && is_legal_file {
+        if (is_file || (is_dir && state.include_directories)) && is_legal_file {
             new_working_files.push(working_file);
         }
     }
     state.working_files = new_working_files;
+    // the tasks have to run from the start on new working files
+    state.preview_cache = PreviewCache::default();
 }
 
 // fn state_update_target_directory() {}
 
-pub fn resolve_workingfile_duplicates(state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
+fn suffixed_target(target: &Path, n: usize) -> PathBuf {
+    let (parent, stem, extension) = (
+        target.parent(),
+        target.file_stem().unwrap_or_default().to_string_lossy(),
+        target.extension().map(|e| e.to_string_lossy()),
+    );
 
+    let new_filename = match extension {
+        Some(ext) => format!("{}_{:04}.{}", stem, n, ext),
+        None => format!("{}_{:04}", stem, n),
+    };
+
+    match parent {
+        Some(p) => p.join(new_filename),     // Move happens here
+        None => PathBuf::from(new_filename), // Move happens here
+    }
+}
+
+/// Files a filter removed aren't written, so they don't push a suffix onto the files that are.
+pub fn resolve_workingfile_duplicates(state: &mut AppState) {
     state.working_files = state
         .working_files
         .drain(..)
         .scan(HashMap::<PathBuf, usize>::new(), |seen, working_file| {
+            if !working_file.active {
+                return Some(working_file);
+            }
             let count = seen.entry(working_file.target.clone()).or_insert(0);
             let new_target = match *count {
                 0 => working_file.target.clone(),
-                n => {
-                    let (parent, stem, extension) = (
-                        working_file.target.parent(),
-                        working_file.target.file_stem().unwrap_or_default().to_string_lossy(),
-                        working_file.target.extension().map(|e| e.to_string_lossy()),
-                    );
-
-                    let new_filename = match extension {
-                        Some(ext) => format!("{}_{:04}.{}", stem, n, ext),
-                        None => format!("{}_{:04}", stem, n),
-                    };
-
-                    match parent {
-                        Some(p) => p.join(new_filename),     // Move happens here
-                        None => PathBuf::from(new_filename), // Move happens here
-                    }
-                }
+                n => suffixed_target(&working_file.target, n),
             };
             *count += 1;
-            Some(WorkingFile {
-                source: working_file.source,
-                target: new_target,
-                active: working_file.active,
-                stable_id: working_file.stable_id,
-            })
+            Some(WorkingFile { target: new_target, ..working_file })
         })
         .collect();
 }
 
-pub fn convert_working_files_to_file_status(state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
-    let state = &mut *state;
+/// Characters that aren't allowed in a file name on at least one of the platforms we ship on.
+pub(crate) const ILLEGAL_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Replaces the characters a file name can't hold with `_`, for values that come from outside the name like tags and dates.
+pub(crate) fn replace_illegal_chars(value: &str) -> String {
+    value.chars().map(|c| if ILLEGAL_CHARS.contains(&c) || c.is_control() { '_' } else { c }).collect()
+}
+
+/// The new name as the tasks wrote it, relative to the folder of the source. Only more than a file name
+/// when a task put a separator or `..` into the name.
+pub(crate) fn target_name(file: &WorkingFile) -> &Path {
+    file.source.parent().and_then(|parent| file.target.strip_prefix(parent).ok()).unwrap_or(&file.target)
+}
+
+/// Names that can't exist on at least one of the platforms we ship on.
+fn is_valid_file_name(file_name: &str) -> bool {
+    !file_name.is_empty()
+        && file_name != "."
+        && file_name != ".."
+        && !file_name.chars().any(|c| ILLEGAL_CHARS.contains(&c) || c.is_control())
+}
+
+/// A case-only rename on a case-insensitive disk points at the file itself, that isn't a conflict.
+pub(crate) fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// A destination that is the source of another file only conflicts when that file stays where it is.
+/// Renames and moves free their source up, the executor orders them so nothing gets overwritten.
+fn conflict_at(destination: &Path, source: &Path, batch_sources: &HashSet<PathBuf>, frees_sources: bool) -> Conflict {
+    if batch_sources.contains(destination) {
+        if frees_sources {
+            Conflict::None
+        } else {
+            Conflict::CollidesWithSource
+        }
+    } else if destination.symlink_metadata().is_ok() && !is_same_file(destination, source) {
+        Conflict::ExistsOnDisk
+    } else {
+        Conflict::None
+    }
+}
+
+/// A folder can't end up inside itself, and nothing can be moved into a folder of the batch that moves away,
+/// except its own contents which move along with it.
+fn lands_in_moving_folder(destination: &Path, source: &Path, moving_folders: &HashSet<PathBuf>) -> bool {
+    destination.ancestors().skip(1).any(|folder| folder == source || (moving_folders.contains(folder) && !source.starts_with(folder)))
+}
+
+/// Gives the file the first numbered target that is free everywhere. Returns false if there is none.
+fn auto_suffix_target(
+    file: &mut WorkingFile,
+    output: &Output,
+    planned: &mut HashSet<PathBuf>,
+    batch_sources: &HashSet<PathBuf>,
+) -> bool {
+    let base_target = file.target.clone();
+
+    for n in 1.. {
+        file.target = suffixed_target(&base_target, n);
+        let Some(candidate) = destination(file, output) else {
+            break;
+        };
+
+        // a suffixed name never takes a batch source, even one that moves away
+        if !planned.contains(&candidate) && conflict_at(&candidate, &file.source, batch_sources, false) == Conflict::None {
+            planned.insert(candidate);
+            file.conflict = Conflict::None;
+            return true;
+        }
+    }
+
+    file.target = base_target;
+    false
+}
+
+/// Checks every active file's destination against the disk and the rest of the batch.
+/// With ConflictPolicy::AutoSuffix the target is renamed to the next free name instead.
+pub fn detect_workingfile_conflicts(state: &mut AppState) {
+    let output = state.output.clone();
+    let conflict_policy = state.conflict_policy;
+    let auto_suffix = conflict_policy == ConflictPolicy::AutoSuffix;
+    // a copy leaves its source in place, so only renames and moves can hand a path over to another file
+    let frees_sources = !matches!(output, Output::Copy { .. });
+
+    let batch_sources: HashSet<PathBuf> =
+        state.working_files.iter().filter(|file| file.active).map(|file| file.source.clone()).collect();
+    let mut planned: HashSet<PathBuf> = state.working_files.iter().filter_map(|file| destination(file, &output)).collect();
+    // NOTE: a copied folder stays where it is, only renames and moves take their folder away
+    let moving_folders: HashSet<PathBuf> = state
+        .working_files
+        .iter()
+        .filter(|file| frees_sources && file.active && file.is_dir && destination(file, &output).is_some_and(|path| path != file.source))
+        .map(|file| file.source.clone())
+        .collect();
+    // copies and moves of files with the same name from different folders land on the same destination
+    let mut claimed: HashSet<PathBuf> = HashSet::new();
+
+    for file in state.working_files.iter_mut() {
+        file.conflict = Conflict::None;
+        if !file.active {
+            continue;
+        }
+
+        let Some(file_destination) = destination(file, &output) else {
+            file.conflict = Conflict::InvalidName;
+            continue;
+        };
+        let file_name = file_destination.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+        // NOTE: a name with a separator or `..` in it would take the file out of its folder
+        if !is_valid_file_name(&file_name) || file.target.parent() != file.source.parent() {
+            file.conflict = Conflict::InvalidName;
+        } else if lands_in_moving_folder(&file_destination, &file.source, &moving_folders) {
+            file.conflict = Conflict::InsideMovingFolder;
+        } else if claimed.contains(&file_destination) {
+            file.conflict = Conflict::CollidesWithSource;
+        } else if file_destination != file.source {
+            file.conflict = conflict_at(&file_destination, &file.source, &batch_sources, frees_sources);
+        }
+
+        if auto_suffix && matches!(file.conflict, Conflict::ExistsOnDisk | Conflict::CollidesWithSource) {
+            auto_suffix_target(file, &output, &mut planned, &batch_sources);
+        } else {
+            planned.insert(file_destination.clone());
+        }
+        if !blocked_by_conflict(file, conflict_policy) {
+            claimed.extend(destination(file, &output));
+        }
+    }
+
+    if !frees_sources {
+        return;
+    }
+
+    // a file that won't be renamed keeps its path taken, and whoever wanted that path is stuck too
+    loop {
+        let staying: HashSet<PathBuf> = state
+            .working_files
+            .iter()
+            .filter(|file| file.active && blocked_by_conflict(file, conflict_policy))
+            .map(|file| file.source.clone())
+            .collect();
+        let mut changed = false;
+
+        for file in state.working_files.iter_mut().filter(|file| file.active && file.conflict == Conflict::None) {
+            let Some(file_destination) = destination(file, &output) else {
+                continue;
+            };
+            if file_destination == file.source || !staying.contains(&file_destination) {
+                continue;
+            }
+
+            file.conflict = Conflict::ExistsOnDisk;
+            if !(auto_suffix && auto_suffix_target(file, &output, &mut planned, &batch_sources)) {
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+}
+
+pub fn convert_working_files_to_file_status(state: &mut AppState) {
     let mut file_statuses: Vec<FileStatus> = Vec::with_capacity(state.working_files.len());
     let mut filtered_count: usize = 0;
+    let mut conflict_count: usize = 0;
 
     for (index, working_file) in state.working_files.iter().enumerate() {
         if !working_file.active {
             filtered_count += 1;
+        } else if working_file.conflict != Conflict::None {
+            conflict_count += 1;
         }
         let file_status = FileStatus {
-            old_file_name: working_file.source.file_name().unwrap().to_string_lossy().into_owned(),
-            new_file_name: working_file.target.file_name().unwrap().to_string_lossy().into_owned(),
+            old_file_name: working_file.source.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            new_file_name: target_name(working_file).to_string_lossy().into_owned(),
+            is_dir: working_file.is_dir,
             active: working_file.active,
             selected: false,
             stable_id: index,
+            conflict: working_file.conflict,
+            duplicate_group: working_file.duplicate_group,
         };
         file_statuses.push(file_status);
     }
     state.file_statuses = file_statuses;
     state.filtered_count = filtered_count;
+    state.conflict_count = conflict_count;
 }
 
-pub fn apply_selections_to_filestatuses(state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
+pub fn apply_selections_to_filestatuses(state: &mut AppState) {
     let selected = state.selected_filestatuses.clone();
     let file_statuses = &mut state.file_statuses;
 
@@ -236,10 +463,7 @@ pub fn apply_selections_to_filestatuses(state: &State<'_, Mutex<AppState>>) {
     }
 }
 
-pub fn apply_search(state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
-    let state = &mut *state;
-
+pub fn apply_search(state: &mut AppState) {
     if state.search.is_empty() {
         state.filtered_filestatus_indices = None;
     } else {
@@ -259,11 +483,26 @@ pub fn apply_search(state: &State<'_, Mutex<AppState>>) {
     };
 }
 
-pub fn build_response(state: &State<'_, Mutex<AppState>>) -> FileStatusResponse {
-    let state = state.lock().unwrap();
+pub fn build_stats(state: &AppState) -> FileStatusStats {
+    FileStatusStats {
+        total: state.file_statuses.len(),
+        visible: state.filtered_filestatus_indices.as_ref().map_or(state.file_statuses.len(), |indices| indices.len()),
+        selected: state.selected_filestatuses.as_ref().map_or(0, |s| s.len()),
+        filtered: state.filtered_count,
+        ready: state.file_statuses.len() - state.filtered_count,
+        conflicts: state.conflict_count,
+    }
+}
+
+/// Only the rows of the status window are cloned into the response, the rest stay in the AppState.
+pub fn build_response(state: &AppState) -> FileStatusResponse {
+    let stats = build_stats(state);
+    // NOTE: the list may have shrunk since the window was asked for, the window then ends at the last row
+    let offset = state.status_window.offset.min(stats.visible.saturating_sub(state.status_window.limit));
+    let end = offset.saturating_add(state.status_window.limit).min(stats.visible);
 
     let statuses = if let Some(indices) = &state.filtered_filestatus_indices {
-        indices
+        indices[offset..end]
             .iter()
             .map(|index| {
                 state
@@ -274,16 +513,8 @@ pub fn build_response(state: &State<'_, Mutex<AppState>>) -> FileStatusResponse
             })
             .collect()
     } else {
-        state.file_statuses.clone()
+        state.file_statuses[offset..end].to_vec()
     };
 
-    FileStatusResponse {
-        statuses,
-        stats: FileStatusStats {
-            total: state.file_statuses.len(),
-            selected: state.selected_filestatuses.as_ref().map_or(0, |s| s.len()),
-            filtered: state.filtered_count,
-            ready: state.file_statuses.len() - state.filtered_count,
-        },
-    }
+    FileStatusResponse { offset, statuses, stats, task_errors: state.task_errors.clone() }
 }
@@ -0,0 +1,5 @@
+// NOTE: the app binary has no console on Windows, so the command line interface is its own binary
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    std::process::exit(filera_lib::run_cli(&args));
+}
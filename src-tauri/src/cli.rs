@@ -0,0 +1,240 @@
+use crate::journal::{record_batch, JournalBatch};
+use crate::template::read_template;
+use crate::{
+    Conflict, ConflictPolicy, ExecutionProgress, FileOutcome, FileStatus, FolderOptions, MoveVerification, Output, Path, Session,
+    SortOptions, Symlinks,
+};
+
+const USAGE: &str = "\
+Usage: filera-cli rename --template <file.json> [options] <paths>...
+
+Runs a saved template on files and folders without opening the app.
+
+Options:
+  --template <file>     template exported from Filera (required)
+  --dry-run             print the preview table and exit without renaming
+  --output <mode>       replace, copy or move (defaults to the template output)
+  --directory <dir>     output directory for copy and move
+  --on-conflict <mode>  skip, suffix, overwrite or abort when a new name is taken (defaults to skip)
+  --verify <mode>       size or checksum, how moves to another filesystem check the copy (defaults to size)
+  --include-dirs        rename the folders themselves too, not only the files in them
+  --max-depth <n>       load folders only n levels deep, 1 is only what is directly inside
+  --skip-hidden         leave out hidden files and folders
+  --symlinks <mode>     keep, follow or skip symbolic links (defaults to keep)
+  --use-ignore-files    leave out what .gitignore and .ignore files exclude
+  --sort <key>          name, modified, created, size, type, path or parent (defaults to name)
+  --descending          reverse the sort order
+  --then-by <keys>      comma separated sort keys for files the sort key ties on, like type,name
+  --case-sensitive      sort uppercase and lowercase names apart
+  --no-natural          sort digits as text, so file10 comes before file2
+  --journal <file>      record the batch in this undo journal
+  -h, --help            print this help";
+
+#[derive(Debug, Default)]
+struct RenameArgs {
+    template: Option<String>,
+    dry_run: bool,
+    output: Option<String>,
+    directory: Option<String>,
+    on_conflict: Option<String>,
+    verify: Option<String>,
+    include_dirs: bool,
+    max_depth: Option<String>,
+    skip_hidden: bool,
+    symlinks: Option<String>,
+    use_ignore_files: bool,
+    sort: Option<String>,
+    descending: bool,
+    then_by: Option<String>,
+    case_sensitive: bool,
+    no_natural: bool,
+    journal: Option<String>,
+    paths: Vec<String>,
+}
+
+fn parse_rename_args(args: &[String]) -> Result<RenameArgs, String> {
+    let mut parsed = RenameArgs::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().cloned().ok_or(format!("{flag} needs a value"));
+
+        match arg.as_str() {
+            "--template" => parsed.template = Some(value(arg)?),
+            "--dry-run" => parsed.dry_run = true,
+            "--output" => parsed.output = Some(value(arg)?),
+            "--directory" => parsed.directory = Some(value(arg)?),
+            "--on-conflict" => parsed.on_conflict = Some(value(arg)?),
+            "--verify" => parsed.verify = Some(value(arg)?),
+            "--include-dirs" => parsed.include_dirs = true,
+            "--max-depth" => parsed.max_depth = Some(value(arg)?),
+            "--skip-hidden" => parsed.skip_hidden = true,
+            "--symlinks" => parsed.symlinks = Some(value(arg)?),
+            "--use-ignore-files" => parsed.use_ignore_files = true,
+            "--sort" => parsed.sort = Some(value(arg)?),
+            "--descending" => parsed.descending = true,
+            "--then-by" => parsed.then_by = Some(value(arg)?),
+            "--case-sensitive" => parsed.case_sensitive = true,
+            "--no-natural" => parsed.no_natural = true,
+            "--journal" => parsed.journal = Some(value(arg)?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {flag}")),
+            path => parsed.paths.push(path.to_string()),
+        }
+    }
+
+    if parsed.template.is_none() {
+        return Err("--template is required".to_string());
+    }
+    if parsed.paths.is_empty() {
+        return Err("no files or folders were given".to_string());
+    }
+
+    Ok(parsed)
+}
+
+fn parse_conflict_policy(on_conflict: Option<&str>) -> Result<ConflictPolicy, String> {
+    match on_conflict {
+        None | Some("skip") => Ok(ConflictPolicy::Skip),
+        Some("suffix") => Ok(ConflictPolicy::AutoSuffix),
+        Some("overwrite") => Ok(ConflictPolicy::Overwrite),
+        Some("abort") => Ok(ConflictPolicy::Abort),
+        Some(other) => Err(format!("unknown conflict mode {other}")),
+    }
+}
+
+fn parse_move_verification(verify: Option<&str>) -> Result<MoveVerification, String> {
+    match verify {
+        None | Some("size") => Ok(MoveVerification::Size),
+        Some("checksum") => Ok(MoveVerification::Checksum),
+        Some(other) => Err(format!("unknown verify mode {other}")),
+    }
+}
+
+fn parse_folder_options(args: &RenameArgs) -> Result<FolderOptions, String> {
+    let max_depth = match &args.max_depth {
+        None => None,
+        Some(depth) => Some(depth.parse::<usize>().map_err(|_| format!("--max-depth needs a number, not {depth}"))?),
+    };
+    let symlinks = match args.symlinks.as_deref() {
+        None | Some("keep") => Symlinks::Keep,
+        Some("follow") => Symlinks::Follow,
+        Some("skip") => Symlinks::Skip,
+        Some(other) => return Err(format!("unknown symlinks mode {other}")),
+    };
+
+    Ok(FolderOptions { max_depth, skip_hidden: args.skip_hidden, symlinks, use_ignore_files: args.use_ignore_files })
+}
+
+/// Only moves to another filesystem report bytes, a plain rename is too quick to show anything.
+fn print_progress(progress: ExecutionProgress) {
+    if progress.bytes_total == 0 {
+        return;
+    }
+    let percent = progress.bytes_done * 100 / progress.bytes_total;
+    eprint!("\r[{}/{}] copying {} {percent:>3}%", progress.files_done + 1, progress.files_total, progress.file);
+    if progress.bytes_done == progress.bytes_total {
+        eprintln!();
+    }
+}
+
+fn print_preview(file_statuses: &[FileStatus]) {
+    let width = file_statuses.iter().map(|s| s.old_file_name.chars().count()).max().unwrap_or(0);
+
+    for file_status in file_statuses {
+        if !file_status.active {
+            println!("  {:width$}      (filtered)", file_status.old_file_name);
+        } else if file_status.conflict != Conflict::None {
+            println!("  {:width$}  ->  {}  ({:?})", file_status.old_file_name, file_status.new_file_name, file_status.conflict);
+        } else {
+            println!("  {:width$}  ->  {}", file_status.old_file_name, file_status.new_file_name);
+        }
+    }
+}
+
+fn rename(args: &[String]) -> Result<(), String> {
+    let args = parse_rename_args(args)?;
+    let template = read_template(Path::new(args.template.as_deref().unwrap_or_default()))?;
+    let conflict_policy = parse_conflict_policy(args.on_conflict.as_deref())?;
+    let move_verification = parse_move_verification(args.verify.as_deref())?;
+    let folder_options = parse_folder_options(&args)?;
+
+    let output = match &args.output {
+        Some(choice) => Output::from_choice(choice, args.directory.as_deref().unwrap_or_default()),
+        None => template.output.clone(),
+    };
+    if let Output::Copy { directory } | Output::Move { directory } = &output {
+        if !Path::new(directory).is_dir() {
+            return Err(format!("output directory \"{directory}\" does not exist"));
+        }
+    }
+
+    // same pipeline the app runs when files are opened
+    let mut session = Session::new();
+    session.set_sort(args.sort.as_deref().unwrap_or_default(), !args.descending);
+    session.set_sort_options(SortOptions {
+        natural: !args.no_natural,
+        case_insensitive: !args.case_sensitive,
+        then_by: args.then_by.iter().flat_map(|keys| keys.split(',')).map(|key| key.trim().to_string()).collect(),
+    });
+    session.set_tasks(template.tasks);
+    session.set_output(output.clone());
+    session.set_conflict_policy(conflict_policy);
+    session.set_move_verification(move_verification);
+    session.set_include_directories(args.include_dirs);
+    session.set_folder_options(folder_options);
+    let response = session.open_paths(args.paths);
+
+    if let Some(task_error) = response.task_errors.first() {
+        return Err(format!("task {} is invalid: {}", task_error.task_index + 1, task_error.message));
+    }
+
+    print_preview(session.file_statuses());
+
+    let active = response.stats.ready;
+    if args.dry_run {
+        println!("\n{active} of {} files would be renamed (dry run)", response.stats.total);
+        if response.stats.conflicts > 0 {
+            println!("{} of them have naming conflicts", response.stats.conflicts);
+        }
+        return Ok(());
+    }
+
+    let (report, journal_entries) = session.execute_with_progress(&mut print_progress)?;
+
+    if let Some(journal) = &args.journal {
+        record_batch(Path::new(journal), JournalBatch::new(output, journal_entries))?;
+    }
+
+    for file in &report.files {
+        match &file.outcome {
+            FileOutcome::Ok => {}
+            FileOutcome::Skipped { reason } => eprintln!("skipped {}: {reason}", file.source),
+            FileOutcome::Failed { kind, message } => eprintln!("failed {}: {message} ({kind})", file.source),
+        }
+    }
+
+    println!("\n{} of {active} files renamed", report.succeeded);
+    if report.failed > 0 || report.skipped > 0 {
+        return Err(format!("{} files failed and {} were skipped", report.failed, report.skipped));
+    }
+    Ok(())
+}
+
+/// Entry point for `filera <command>`, returns the process exit code.
+pub fn run_cli(args: &[String]) -> i32 {
+    let wants_help = args.iter().any(|arg| arg == "-h" || arg == "--help");
+
+    match args.first().map(String::as_str) {
+        Some("rename") if !wants_help => match rename(&args[1..]) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("error: {e}");
+                1
+            }
+        },
+        _ => {
+            println!("{USAGE}");
+            0
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::process_tasks::{task_condition, PreviewCache};
+use crate::{AppState, HashMap, Path, PathBuf, Task};
+use std::fs::File;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+/// A file's content hash, kept with the size and modified time it was read at. A file that changed since is read again.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CachedHash {
+    len: u64,
+    modified: Option<SystemTime>,
+    hash: String,
+}
+
+/// Streams the file through blake3, the file is never read into memory at once.
+fn hash_file(path: &Path) -> Option<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path).ok()?).ok()?;
+    Some(hasher.finalize().to_hex().to_string())
+}
+
+/// Hashes the files on one thread per core. Each thread takes the next file when it is done,
+/// so one large file doesn't hold up the others.
+fn hash_files(paths: &[PathBuf]) -> Vec<Option<String>> {
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get()).min(paths.len());
+    let next = AtomicUsize::new(0);
+
+    let mut hashes: Vec<Option<String>> = vec![None; paths.len()];
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut hashed: Vec<(usize, Option<String>)> = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(index) else {
+                            break;
+                        };
+                        hashed.push((index, hash_file(path)));
+                    }
+                    hashed
+                })
+            })
+            .collect();
+        for handle in handles {
+            for (index, hash) in handle.join().unwrap_or_default() {
+                hashes[index] = hash;
+            }
+        }
+    });
+    hashes
+}
+
+/// Whether the task or a filter inside it keeps or drops files by their copies.
+fn uses_duplicates(task: &Task) -> bool {
+    match task {
+        Task::FilterDuplicates { .. } => true,
+        Task::FilterGroup { filters, .. } => filters.iter().any(uses_duplicates),
+        _ => task_condition(task).is_some_and(uses_duplicates),
+    }
+}
+
+/// Sorts the loaded files into groups of identical content, when the find duplicates option or a task needs it.
+pub fn hash_working_files(state: &mut AppState) {
+    let previous: Vec<(Option<String>, Option<usize>, usize)> = state
+        .working_files
+        .iter_mut()
+        .map(|file| (file.content_hash.take(), file.duplicate_group.take(), std::mem::take(&mut file.copy_index)))
+        .collect();
+
+    group_duplicates(state);
+
+    // NOTE: the preview kept from the last run is only good for the copies it was made with
+    let unchanged = previous.iter().zip(&state.working_files).all(|((content_hash, duplicate_group, copy_index), file)| {
+        file.content_hash == *content_hash && file.duplicate_group == *duplicate_group && file.copy_index == *copy_index
+    });
+    if !unchanged {
+        state.preview_cache = PreviewCache::default();
+    }
+}
+
+/// Only files that share their size with another one can be copies, every file is hashed only for the `{hash}` token.
+fn group_duplicates(state: &mut AppState) {
+    let hash_token = state.tasks.iter().any(|task| matches!(task, Task::Template { template, .. } if template.contains("{hash")));
+    if !(hash_token || state.find_duplicates || state.tasks.iter().any(uses_duplicates)) {
+        return;
+    }
+
+    let metadata: Vec<Option<(u64, Option<SystemTime>)>> = state
+        .working_files
+        .iter()
+        .map(|file| match file.is_dir {
+            true => None,
+            false => file.metadata.map(|metadata| (metadata.len, metadata.modified)),
+        })
+        .collect();
+    let mut size_counts: HashMap<u64, usize> = HashMap::new();
+    for (len, _) in metadata.iter().flatten() {
+        *size_counts.entry(*len).or_insert(0) += 1;
+    }
+
+    let candidates: Vec<usize> = (0..state.working_files.len())
+        .filter(|&index| metadata[index].is_some_and(|(len, _)| hash_token || size_counts[&len] > 1))
+        .collect();
+
+    // NOTE: only files that are new or changed since the last pass are read again
+    let stale: Vec<usize> = candidates
+        .iter()
+        .copied()
+        .filter(|&index| {
+            let (len, modified) = metadata[index].unwrap_or_default();
+            let cached = state.hash_cache.get(&state.working_files[index].source);
+            !cached.is_some_and(|cached| cached.len == len && cached.modified == modified)
+        })
+        .collect();
+    let stale_paths: Vec<PathBuf> = stale.iter().map(|&index| state.working_files[index].source.clone()).collect();
+
+    for (index, hash) in stale.iter().zip(hash_files(&stale_paths)) {
+        let source = state.working_files[*index].source.clone();
+        let (len, modified) = metadata[*index].unwrap_or_default();
+        match hash {
+            Some(hash) => state.hash_cache.insert(source, CachedHash { len, modified, hash }),
+            None => state.hash_cache.remove(&source),
+        };
+    }
+
+    let mut copies: HashMap<String, usize> = HashMap::new();
+    for &index in &candidates {
+        let file = &mut state.working_files[index];
+        file.content_hash = state.hash_cache.get(&file.source).map(|cached| cached.hash.clone());
+        if let Some(hash) = &file.content_hash {
+            *copies.entry(hash.clone()).or_insert(0) += 1;
+        }
+    }
+
+    // groups are numbered in the sort order, from 1
+    let mut groups: HashMap<String, (usize, usize)> = HashMap::new();
+    for file in state.working_files.iter_mut() {
+        let Some(hash) = &file.content_hash else {
+            continue;
+        };
+        if copies[hash] < 2 {
+            continue;
+        }
+        let next_group = groups.len() + 1;
+        let (group, seen) = groups.entry(hash.clone()).or_insert((next_group, 0));
+        file.duplicate_group = Some(*group);
+        file.copy_index = *seen;
+        *seen += 1;
+    }
+}
@@ -0,0 +1,493 @@
+use crate::atomics::is_same_file;
+use crate::journal::JournalEntry;
+use crate::transfer::{move_file, temporary_path};
+use crate::{
+    Conflict, ConflictPolicy, ExecutionProgress, ExecutionReport, FileOutcome, FileReport, HashMap, MoveVerification, Output, Path,
+    PathBuf, WorkingFile,
+};
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{fs::copy, fs::rename};
+
+/// The error of the files a cancelled batch never got to.
+#[derive(Debug)]
+struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the batch was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+fn cancelled_error() -> Error {
+    Error::new(ErrorKind::Interrupted, Cancelled)
+}
+
+fn is_cancelled(e: &Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<Cancelled>())
+}
+
+/// Where the file ends up on disk. Copy and move keep the new file name, but in the output directory.
+pub fn destination(file: &WorkingFile, output: &Output) -> Option<PathBuf> {
+    match output {
+        Output::Replace => Some(file.target.clone()),
+        Output::Copy { directory } | Output::Move { directory } => Some(PathBuf::from(directory).join(file.target.file_name()?)),
+    }
+}
+
+/// Overwrite is the only policy that lets a file through onto an existing path.
+pub(crate) fn blocked_by_conflict(file: &WorkingFile, conflict_policy: ConflictPolicy) -> bool {
+    match file.conflict {
+        Conflict::None => false,
+        Conflict::ExistsOnDisk => conflict_policy != ConflictPolicy::Overwrite,
+        Conflict::CollidesWithSource | Conflict::InvalidName | Conflict::InsideMovingFolder => true,
+    }
+}
+
+/// Renames (source, destination) pairs that may point at each other's sources.
+///
+/// A file only moves once the file sitting on its destination has moved away, so chains like
+/// 1→2, 2→3 run back to front. When only cycles are left (1→2, 2→1) one file of the cycle is
+/// parked under a temporary name first. A folder waits until everything of the batch inside it
+/// is done, so the deepest paths go first and the paths of its contents stay valid.
+/// `move_file` does the actual move of a file onto its destination. Once `cancel` is set the files
+/// that haven't moved yet are left alone, after the files parked under a temporary name got their new name.
+/// Returns one result per pair, in the order they were given.
+pub fn rename_in_dependency_order(
+    renames: &[(PathBuf, PathBuf)],
+    overwrite: bool,
+    cancel: &AtomicBool,
+    move_file: &mut dyn FnMut(&Path, &Path) -> std::io::Result<()>,
+) -> Vec<std::io::Result<()>> {
+    let sources: HashMap<&PathBuf, usize> = renames.iter().enumerate().map(|(index, (source, _))| (source, index)).collect();
+    // blockers[i] is the file of the batch that currently sits on the destination of file i
+    let blockers: Vec<Option<usize>> = renames
+        .iter()
+        .enumerate()
+        .map(|(index, (_, destination))| sources.get(destination).copied().filter(|&blocker| blocker != index))
+        .collect();
+    // containers[i] are the folders of the batch that pair i comes from or goes into.
+    // A folder moving into itself isn't its own content, it would wait on itself forever
+    let folders: HashMap<&Path, usize> = renames
+        .iter()
+        .enumerate()
+        .filter(|(_, (source, _))| source.is_dir())
+        .map(|(index, (source, _))| (source.as_path(), index))
+        .collect();
+    let containers: Vec<Vec<usize>> = renames
+        .iter()
+        .enumerate()
+        .map(|(index, (source, destination))| {
+            let mut containers: Vec<usize> = source
+                .ancestors()
+                .skip(1)
+                .chain(destination.ancestors().skip(1))
+                .filter_map(|folder| folders.get(folder).copied())
+                .filter(|&folder| folder != index)
+                .collect();
+            containers.sort_unstable();
+            containers.dedup();
+            containers
+        })
+        .collect();
+
+    // waiting[i] counts what pair i still waits for, the file on its destination and everything inside it.
+    // A pair is released once its blocker moved away or failed, and once each of its contents is done
+    let mut waiting: Vec<usize> = blockers.iter().map(|blocker| usize::from(blocker.is_some())).collect();
+    let mut waiters: Vec<Vec<usize>> = vec![Vec::new(); renames.len()];
+    for (index, blocker) in blockers.iter().enumerate() {
+        if let Some(blocker) = blocker {
+            waiters[*blocker].push(index);
+        }
+    }
+    for &folder in containers.iter().flatten() {
+        waiting[folder] += 1;
+    }
+    let has_contents: Vec<bool> =
+        waiting.iter().zip(&blockers).map(|(&waiting, blocker)| waiting > usize::from(blocker.is_some())).collect();
+
+    // the deepest paths go first, pairs that are ready at the same time run in this order
+    let mut order: Vec<usize> = (0..renames.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(renames[index].0.components().count()));
+    let mut rank = vec![0; renames.len()];
+    for (position, &index) in order.iter().enumerate() {
+        rank[index] = position;
+    }
+    let mut ready: BinaryHeap<Reverse<usize>> =
+        (0..renames.len()).filter(|&index| waiting[index] == 0).map(|index| Reverse(rank[index])).collect();
+    let release = |pairs: &[usize], waiting: &mut [usize], ready: &mut BinaryHeap<Reverse<usize>>| {
+        for &pair in pairs {
+            waiting[pair] -= 1;
+            if waiting[pair] == 0 {
+                ready.push(Reverse(rank[pair]));
+            }
+        }
+    };
+
+    let mut current: Vec<PathBuf> = renames.iter().map(|(source, _)| source.clone()).collect();
+    let mut vacated = vec![false; renames.len()];
+    let mut results: Vec<Option<std::io::Result<()>>> = renames.iter().map(|_| None).collect();
+    let mut done: usize = 0;
+    let mut parked_pending: usize = 0;
+    // every pair is parked at most once, so the search for the next one to park only moves forward
+    let (mut next_file, mut next_any) = (0, 0);
+
+    while done < renames.len() {
+        while let Some(Reverse(position)) = ready.pop() {
+            let index = order[position];
+            let destination = &renames[index].1;
+            let parked = current[index] != renames[index].0;
+
+            // NOTE: a cycle that was started is finished first, so no file keeps its temporary name
+            let result = if parked_pending == 0 && cancel.load(Ordering::Relaxed) {
+                Err(cancelled_error())
+            } else {
+                let result = match blockers[index] {
+                    Some(blocker) if !vacated[blocker] => Err(Error::new(
+                        ErrorKind::AlreadyExists,
+                        "destination is still taken by a file of this batch that could not be renamed",
+                    )),
+                    // the destination belonged to the batch and is free now
+                    Some(_) => move_file(&current[index], destination),
+                    None if !overwrite && destination.symlink_metadata().is_ok() && !is_same_file(&current[index], destination) => {
+                        Err(Error::new(ErrorKind::AlreadyExists, "destination already exists"))
+                    }
+                    None => move_file(&current[index], destination),
+                };
+                parked_pending -= usize::from(parked);
+                result.map_err(|e| match parked {
+                    true => Error::new(e.kind(), format!("{e}, the file was left at {}", current[index].display())),
+                    false => e,
+                })
+            };
+
+            // whoever waited on this destination can go now, or fails because it stays taken
+            if !vacated[index] {
+                vacated[index] = result.is_ok();
+                release(&waiters[index], &mut waiting, &mut ready);
+            }
+            release(&containers[index], &mut waiting, &mut ready);
+            results[index] = Some(result);
+            done += 1;
+        }
+
+        if done == renames.len() {
+            break;
+        }
+
+        // everything left waits on something else that is left, so these are cycles.
+        // Parking a folder would pull its contents away, so files go first
+        let parkable = |index: usize| results[index].is_none() && !vacated[index];
+        while next_file < order.len() && (!parkable(order[next_file]) || has_contents[order[next_file]]) {
+            next_file += 1;
+        }
+        while next_any < order.len() && !parkable(order[next_any]) {
+            next_any += 1;
+        }
+        let Some(&index) = order.get(next_file).or(order.get(next_any)) else {
+            // nothing can move anymore, the parked files go back to where they came from
+            let parked: Vec<usize> =
+                (0..renames.len()).filter(|&index| results[index].is_none() && current[index] != renames[index].0).collect();
+            for index in parked {
+                results[index] = Some(match rename(&current[index], &renames[index].0) {
+                    Ok(()) => Err(Error::other("file was not renamed, it was put back")),
+                    Err(e) => Err(Error::new(e.kind(), format!("{e}, the file was left at {}", current[index].display()))),
+                });
+            }
+            break;
+        };
+        let parked = temporary_path(&current[index]);
+        match rename(&current[index], &parked) {
+            Ok(()) => {
+                current[index] = parked;
+                vacated[index] = true;
+                parked_pending += 1;
+                release(&waiters[index], &mut waiting, &mut ready);
+            }
+            Err(e) => {
+                release(&waiters[index], &mut waiting, &mut ready);
+                release(&containers[index], &mut waiting, &mut ready);
+                results[index] = Some(Err(e));
+                done += 1;
+            }
+        }
+    }
+
+    results.into_iter().map(|result| result.unwrap_or(Err(Error::other("file was not renamed")))).collect()
+}
+
+fn skip_reason(conflict: Conflict) -> &'static str {
+    match conflict {
+        Conflict::None => "",
+        Conflict::ExistsOnDisk => "a file with the new name already exists",
+        Conflict::CollidesWithSource => "the new name belongs to another file of the batch",
+        Conflict::InvalidName => "the new name is not a valid file name",
+        Conflict::InsideMovingFolder => "the new path is inside a folder that moves",
+    }
+}
+
+fn file_outcome(result: std::io::Result<()>) -> FileOutcome {
+    match result {
+        Ok(()) => FileOutcome::Ok,
+        Err(e) if is_cancelled(&e) => FileOutcome::Skipped { reason: e.to_string() },
+        Err(e) => FileOutcome::Failed { kind: format!("{:?}", e.kind()), message: e.to_string() },
+    }
+}
+
+fn file_report(source: &Path, target: &Path, outcome: FileOutcome) -> FileReport {
+    FileReport { source: source.to_string_lossy().into_owned(), target: target.to_string_lossy().into_owned(), outcome }
+}
+
+/// Rename, copy or move every active working file, calling `progress` after every file and while big moves copy.
+/// Setting `cancel` stops the batch before the next file, the files it didn't get to are reported as skipped.
+/// Returns what happened to each of them, and the journal entries of the files that made it to disk.
+pub fn execute_working_files(
+    working_files: &mut [WorkingFile],
+    output: &Output,
+    conflict_policy: ConflictPolicy,
+    move_verification: MoveVerification,
+    progress: &mut dyn FnMut(ExecutionProgress),
+    cancel: &AtomicBool,
+) -> Result<(ExecutionReport, Vec<JournalEntry>), String> {
+    let mut journal_entries: Vec<JournalEntry> = Vec::new();
+    let mut files: Vec<FileReport> = Vec::new();
+
+    if conflict_policy == ConflictPolicy::Abort {
+        let conflicts = working_files.iter().filter(|file| file.active && blocked_by_conflict(file, conflict_policy)).count();
+        if conflicts > 0 {
+            return Err(format!("{conflicts} files have naming conflicts, nothing was renamed"));
+        }
+    }
+
+    // (source, destination) of every file that gets written, and where its report is
+    let mut jobs: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut job_reports: Vec<usize> = Vec::new();
+    let mut job_folders: Vec<bool> = Vec::new();
+
+    for file in working_files.iter_mut().filter(|file| file.active) {
+        if blocked_by_conflict(file, conflict_policy) {
+            let reason = skip_reason(file.conflict).to_string();
+            files.push(file_report(&file.source, &file.target, FileOutcome::Skipped { reason }));
+            continue;
+        }
+        let Some(destination) = destination(file, output) else {
+            let reason = skip_reason(Conflict::InvalidName).to_string();
+            files.push(file_report(&file.source, &file.target, FileOutcome::Skipped { reason }));
+            continue;
+        };
+        file.target = destination;
+
+        if file.is_dir && matches!(output, Output::Copy { .. }) {
+            let reason = "folders can only be renamed or moved, not copied".to_string();
+            files.push(file_report(&file.source, &file.target, FileOutcome::Skipped { reason }));
+            continue;
+        }
+
+        job_reports.push(files.len());
+        job_folders.push(file.is_dir);
+        files.push(file_report(&file.source, &file.target, FileOutcome::Ok));
+        jobs.push((file.source.clone(), file.target.clone()));
+    }
+
+    let files_total = jobs.len();
+    let mut files_done = 0;
+    let mut files_failed = 0;
+    let mut report_progress = |file: &Path, files_done: usize, files_failed: usize, bytes_done: u64, bytes_total: u64| {
+        let file = file.to_string_lossy().into_owned();
+        progress(ExecutionProgress { file, files_done, files_total, files_failed, bytes_done, bytes_total });
+    };
+
+    let job_results: Vec<std::io::Result<()>> = match output {
+        Output::Copy { .. } => jobs
+            .iter()
+            .map(|(source, target)| {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(cancelled_error());
+                }
+                let copy_result = copy(source, target).map(|_| ());
+                files_done += 1;
+                files_failed += usize::from(copy_result.is_err());
+                report_progress(source, files_done, files_failed, 0, 0);
+                copy_result
+            })
+            .collect(),
+        Output::Replace | Output::Move { .. } => {
+            let overwrite = conflict_policy == ConflictPolicy::Overwrite;
+            rename_in_dependency_order(&jobs, overwrite, cancel, &mut |source, target| {
+                let move_result = move_file(source, target, move_verification, &mut |bytes_done, bytes_total| {
+                    report_progress(source, files_done, files_failed, bytes_done, bytes_total)
+                });
+                files_done += 1;
+                files_failed += usize::from(move_result.is_err());
+                report_progress(source, files_done, files_failed, 0, 0);
+                move_result
+            })
+        }
+    };
+
+    let cancelled = job_results.iter().any(|job_result| job_result.as_ref().is_err_and(is_cancelled));
+    let mut moved_folders: Vec<(&PathBuf, &PathBuf)> = Vec::new();
+    for (((report_index, is_dir), (source, target)), job_result) in job_reports.into_iter().zip(job_folders).zip(&jobs).zip(job_results) {
+        if job_result.is_ok() {
+            journal_entries.push(JournalEntry::new(source, target));
+            if is_dir {
+                moved_folders.push((source, target));
+            }
+        }
+        files[report_index].outcome = file_outcome(job_result);
+    }
+
+    // the journal has to point at where things are now, so undo can find what was inside a moved folder
+    moved_folders.sort_by_key(|(source, _)| std::cmp::Reverse(source.components().count()));
+    for (source, target) in moved_folders {
+        journal_entries.iter_mut().for_each(|entry| entry.relocate(source, target));
+    }
+
+    let report = ExecutionReport {
+        cancelled,
+        succeeded: files.iter().filter(|file| file.outcome == FileOutcome::Ok).count(),
+        skipped: files.iter().filter(|file| matches!(file.outcome, FileOutcome::Skipped { .. })).count(),
+        failed: files.iter().filter(|file| matches!(file.outcome, FileOutcome::Failed { .. })).count(),
+        files,
+    };
+
+    Ok((report, journal_entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn run(renames: &[(PathBuf, PathBuf)]) -> Vec<std::io::Result<()>> {
+        rename_in_dependency_order(renames, false, &AtomicBool::new(false), &mut |from, to| rename(from, to))
+    }
+
+    #[test]
+    fn chain_runs_back_to_front() {
+        let dir = TestDir::new("chain");
+        let renames = [(dir.file("1"), dir.path("2")), (dir.file("2"), dir.path("3"))];
+
+        let results = run(&renames);
+
+        assert!(results.iter().all(Result::is_ok), "{results:?}");
+        assert_eq!(dir.contents("2"), "1");
+        assert_eq!(dir.contents("3"), "2");
+        assert_eq!(dir.listing(), ["2", "3"]);
+    }
+
+    #[test]
+    fn swap_parks_one_file() {
+        let dir = TestDir::new("swap");
+        let renames = [(dir.file("a"), dir.path("b")), (dir.file("b"), dir.path("a"))];
+
+        let results = run(&renames);
+
+        assert!(results.iter().all(Result::is_ok), "{results:?}");
+        assert_eq!(dir.contents("a"), "b");
+        assert_eq!(dir.contents("b"), "a");
+        assert_eq!(dir.listing(), ["a", "b"]);
+    }
+
+    #[test]
+    fn three_way_cycle() {
+        let dir = TestDir::new("cycle");
+        let renames = [(dir.file("a"), dir.path("b")), (dir.file("b"), dir.path("c")), (dir.file("c"), dir.path("a"))];
+
+        let results = run(&renames);
+
+        assert!(results.iter().all(Result::is_ok), "{results:?}");
+        assert_eq!(dir.contents("a"), "c");
+        assert_eq!(dir.contents("b"), "a");
+        assert_eq!(dir.contents("c"), "b");
+        assert_eq!(dir.listing(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn long_shift_moves_each_file_once() {
+        // numbers shifted up by one, listed so that every file waits on the one after it
+        let renames: Vec<(PathBuf, PathBuf)> =
+            (0..20_000).map(|n| (PathBuf::from(format!("/nowhere/{n}")), PathBuf::from(format!("/nowhere/{}", n + 1)))).collect();
+        let mut moves = Vec::new();
+
+        let results = rename_in_dependency_order(&renames, false, &AtomicBool::new(false), &mut |from, _| {
+            moves.push(from.to_path_buf());
+            Ok(())
+        });
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(moves.len(), renames.len());
+        assert_eq!(moves.first(), renames.last().map(|(source, _)| source));
+    }
+
+    #[test]
+    fn failed_blocker_keeps_its_destination_taken() {
+        let dir = TestDir::new("blocker");
+        dir.file("c");
+        let renames = [(dir.file("a"), dir.path("b")), (dir.file("b"), dir.path("c"))];
+
+        let results = run(&renames);
+
+        assert_eq!(results[1].as_ref().unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(results[0].as_ref().unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(dir.contents("a"), "a");
+        assert_eq!(dir.contents("b"), "b");
+        assert_eq!(dir.contents("c"), "c");
+    }
+
+    #[test]
+    fn cancel_finishes_the_cycle_and_skips_the_rest() {
+        let dir = TestDir::new("cancel");
+        // the folder waits for the cycle inside it, so it is still to do when the cycle starts moving files
+        let renames = [
+            (dir.file("f/a"), dir.path("f/b")),
+            (dir.file("f/b"), dir.path("f/c")),
+            (dir.file("f/c"), dir.path("f/a")),
+            (dir.path("f"), dir.path("g")),
+        ];
+        let cancel = AtomicBool::new(false);
+
+        let results = rename_in_dependency_order(&renames, false, &cancel, &mut |from, to| {
+            cancel.store(true, Ordering::Relaxed);
+            rename(from, to)
+        });
+
+        assert!(results[..3].iter().all(Result::is_ok), "{results:?}");
+        assert!(is_cancelled(results[3].as_ref().unwrap_err()));
+        assert_eq!(dir.contents("f/a"), "f/c");
+        assert_eq!(dir.contents("f/b"), "f/a");
+        assert_eq!(dir.contents("f/c"), "f/b");
+        assert_eq!(dir.listing(), ["f", "f/a", "f/b", "f/c"]);
+    }
+
+    #[test]
+    fn folder_waits_for_its_contents() {
+        let dir = TestDir::new("folder");
+        let renames = [(dir.folder("f"), dir.path("g")), (dir.file("f/x"), dir.path("f/y"))];
+
+        let results = run(&renames);
+
+        assert!(results.iter().all(Result::is_ok), "{results:?}");
+        assert_eq!(dir.contents("g/y"), "f/x");
+        assert_eq!(dir.listing(), ["g", "g/y"]);
+    }
+
+    #[test]
+    fn folder_moved_into_itself_fails_without_losing_files() {
+        let dir = TestDir::new("inside");
+        dir.folder("f/sorted");
+        let renames = [(dir.path("f"), dir.path("f/sorted/f")), (dir.file("f/x"), dir.path("f/sorted/x"))];
+
+        let results = run(&renames);
+
+        assert!(results[0].is_err(), "{results:?}");
+        assert!(results[1].is_ok(), "{results:?}");
+        assert_eq!(dir.contents("f/sorted/x"), "f/x");
+        assert_eq!(dir.listing(), ["f", "f/sorted", "f/sorted/x"]);
+    }
+}
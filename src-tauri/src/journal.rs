@@ -0,0 +1,220 @@
+use crate::execute::rename_in_dependency_order;
+use crate::transfer::move_file;
+use crate::{ExecutionProgress, MoveVerification, Output, Path, PathBuf};
+
+use std::fs;
+use std::sync::atomic::AtomicBool;
+use std::time::SystemTime;
+
+const JOURNAL_MAX_BATCHES: usize = 100;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct JournalEntry {
+    source: PathBuf,
+    target: PathBuf,
+    target_len: u64,
+    target_modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct JournalBatch {
+    output: Output,
+    timestamp: SystemTime,
+    pub(crate) entries: Vec<JournalEntry>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Default)]
+pub struct Journal {
+    pub(crate) batches: Vec<JournalBatch>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct UndoSkipped {
+    source: String,
+    target: String,
+    reason: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Default)]
+pub struct UndoReport {
+    restored: usize,
+    skipped: Vec<UndoSkipped>,
+}
+
+impl JournalEntry {
+    /// Snapshot the target right after it was written, so undo can tell if it was changed since.
+    pub fn new(source: &Path, target: &Path) -> Self {
+        let mut entry = JournalEntry { source: source.to_path_buf(), target: target.to_path_buf(), target_len: 0, target_modified: None };
+        entry.snapshot_target();
+        entry
+    }
+
+    fn snapshot_target(&mut self) {
+        let metadata = fs::metadata(&self.target).ok();
+        self.target_len = metadata.as_ref().map_or(0, |m| m.len());
+        self.target_modified = metadata.and_then(|m| m.modified().ok());
+    }
+
+    /// Follows a folder that was moved after this entry was written, if the entry points inside it.
+    /// The target is read again where it is now, the old path no longer exists.
+    pub fn relocate(&mut self, from: &Path, to: &Path) {
+        let mut moved = false;
+        for path in [&mut self.source, &mut self.target] {
+            if let Ok(relative) = path.strip_prefix(from) {
+                if !relative.as_os_str().is_empty() {
+                    *path = to.join(relative);
+                    moved = true;
+                }
+            }
+        }
+        if moved {
+            self.snapshot_target();
+        }
+    }
+
+    fn target_unchanged(&self) -> bool {
+        match fs::metadata(&self.target) {
+            Ok(metadata) => metadata.len() == self.target_len && metadata.modified().ok() == self.target_modified,
+            Err(_) => false,
+        }
+    }
+}
+
+impl JournalBatch {
+    pub fn new(output: Output, entries: Vec<JournalEntry>) -> Self {
+        JournalBatch { output, timestamp: SystemTime::now(), entries }
+    }
+}
+
+pub fn load_journal(path: &Path) -> Journal {
+    fs::read_to_string(path).ok().and_then(|contents| serde_json::from_str(&contents).ok()).unwrap_or_default()
+}
+
+pub fn save_journal(path: &Path, journal: &Journal) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let contents = serde_json::to_string_pretty(journal).map_err(|e| e.to_string())?;
+    fs::write(path, contents).map_err(|e| e.to_string())
+}
+
+pub fn record_batch(path: &Path, batch: JournalBatch) -> Result<(), String> {
+    if batch.entries.is_empty() {
+        return Ok(());
+    }
+
+    let mut journal = load_journal(path);
+    journal.batches.push(batch);
+
+    let overflow = journal.batches.len().saturating_sub(JOURNAL_MAX_BATCHES);
+    journal.batches.drain(..overflow);
+
+    save_journal(path, &journal)
+}
+
+fn check_entry(entry: &JournalEntry) -> Result<(), String> {
+    if !entry.target.exists() {
+        return Err("target no longer exists".to_string());
+    }
+    if !entry.target_unchanged() {
+        return Err("target was modified after the batch ran".to_string());
+    }
+    Ok(())
+}
+
+/// Reverse a batch. Entries that can't be restored are reported and left alone.
+/// Renames go back through the same ordering as the batch itself, so swapped files swap back.
+/// The returned batch holds the entries that were left, so they can be undone again later.
+/// `progress` is called after every entry and while big moves copy back, like for a batch.
+pub fn undo_batch(batch: &JournalBatch, progress: &mut dyn FnMut(ExecutionProgress)) -> (UndoReport, JournalBatch) {
+    let mut report = UndoReport::default();
+    let mut results: Vec<(usize, Result<(), String>)> = Vec::with_capacity(batch.entries.len());
+    let mut renames: Vec<(usize, (PathBuf, PathBuf))> = Vec::new();
+
+    let files_total = batch.entries.len();
+    let mut files_done = 0;
+    let mut files_failed = 0;
+    let mut report_progress = |file: &Path, files_done: usize, files_failed: usize, bytes_done: u64, bytes_total: u64| {
+        let file = file.to_string_lossy().into_owned();
+        progress(ExecutionProgress { file, files_done, files_total, files_failed, bytes_done, bytes_total });
+    };
+
+    for (index, entry) in batch.entries.iter().enumerate().rev() {
+        let result = match (check_entry(entry), &batch.output) {
+            (Err(reason), _) => Err(reason),
+            // a copy left the original in place, so undoing it only removes the copy
+            (Ok(()), Output::Copy { .. }) => fs::remove_file(&entry.target).map_err(|e| e.to_string()),
+            (Ok(()), Output::Replace | Output::Move { .. }) => {
+                renames.push((index, (entry.target.clone(), entry.source.clone())));
+                continue;
+            }
+        };
+        files_done += 1;
+        files_failed += usize::from(result.is_err());
+        report_progress(&entry.target, files_done, files_failed, 0, 0);
+        results.push((index, result));
+    }
+
+    let pairs: Vec<(PathBuf, PathBuf)> = renames.iter().map(|(_, pair)| pair.clone()).collect();
+    let mut move_back = |target: &Path, source: &Path| {
+        let move_result = move_file(target, source, MoveVerification::Size, &mut |bytes_done, bytes_total| {
+            report_progress(target, files_done, files_failed, bytes_done, bytes_total)
+        });
+        files_done += 1;
+        files_failed += usize::from(move_result.is_err());
+        report_progress(target, files_done, files_failed, 0, 0);
+        move_result
+    };
+    // NOTE: an undo runs to the end, it can't be cancelled
+    let rename_results = rename_in_dependency_order(&pairs, false, &AtomicBool::new(false), &mut move_back)
+        .into_iter()
+        .map(|result| result.map_err(|e| e.to_string()));
+    results.extend(renames.into_iter().map(|(index, _)| index).zip(rename_results));
+
+    let mut left = vec![false; batch.entries.len()];
+    for (index, result) in results {
+        let entry = &batch.entries[index];
+        match result {
+            Ok(()) => report.restored += 1,
+            Err(reason) => {
+                report.skipped.push(UndoSkipped {
+                    source: entry.source.to_string_lossy().into_owned(),
+                    target: entry.target.to_string_lossy().into_owned(),
+                    reason,
+                });
+                left[index] = true;
+            }
+        }
+    }
+
+    let entries = batch.entries.iter().zip(left).filter(|(_, left)| *left).map(|(entry, _)| entry.clone()).collect();
+    (report, JournalBatch { output: batch.output.clone(), timestamp: batch.timestamp, entries })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use crate::{Session, Task};
+
+    #[test]
+    fn undo_restores_a_folder_and_its_contents() {
+        let dir = TestDir::new("undo-folder");
+        dir.file("f/x.txt");
+        let tasks: Vec<Task> = serde_json::from_str(r#"[{"CustomText":{"text":"_new","at_start":false,"active":true}}]"#).unwrap();
+        let mut session = Session::new();
+        session.set_include_directories(true);
+        session.set_tasks(tasks);
+        session.open_paths(vec![dir.path("f").to_string_lossy().to_string()]);
+
+        let (report, journal) = session.execute().unwrap();
+        assert_eq!(report.succeeded, 2);
+        assert_eq!(dir.listing(), ["f_new", "f_new/x_new.txt"]);
+
+        let (undo, remaining) = undo_batch(&JournalBatch::new(Output::Replace, journal), &mut |_| {});
+        assert_eq!(undo.restored, 2, "{:?}", undo.skipped);
+        assert!(remaining.entries.is_empty());
+        assert_eq!(dir.listing(), ["f", "f/x.txt"]);
+        assert_eq!(dir.contents("f/x.txt"), "f/x.txt");
+    }
+}
@@ -1,54 +1,250 @@
 mod atomics;
+mod cli;
+mod content_hash;
+mod execute;
+mod journal;
+mod name_matcher;
+mod name_template;
+mod pipeline;
 mod process_tasks;
+mod template;
+#[cfg(test)]
+mod test_dir;
+mod transfer;
+// NOTE: the user_* modules are the tauri commands of the app window, everything else builds without tauri
+#[cfg(feature = "gui")]
 mod user_filestatus;
+#[cfg(feature = "gui")]
+mod user_journal;
+#[cfg(feature = "gui")]
 mod user_std;
+#[cfg(feature = "gui")]
+mod user_template;
 
+#[cfg(feature = "gui")]
 use user_std::{
-    user_clear_files, user_dialog, user_dragdrop_files, user_notification, user_open_files, user_open_folders, user_rename_files,
-    user_update_search, user_update_sort, user_update_tasks,
+    user_cancel_batch, user_clear_files, user_dialog, user_dragdrop_files, user_notification, user_open_files, user_open_folders,
+    user_refresh_metadata, user_rename_files, user_update_conflict_policy, user_update_find_duplicates, user_update_folder_options,
+    user_update_include_directories, user_update_move_verification, user_update_output, user_update_search, user_update_sort,
+    user_update_sort_options, user_update_tasks,
 };
 
+#[cfg(feature = "gui")]
 use user_filestatus::{
     user_filestatus_click, user_filestatus_ctrl_click, user_filestatus_selection_clear, user_filestatus_selection_delete,
-    user_filestatus_shift_click,
+    user_filestatus_shift_click, user_filestatus_window,
 };
 
+#[cfg(feature = "gui")]
+use user_journal::user_undo_last_batch;
+
+#[cfg(feature = "gui")]
+use user_template::{
+    user_delete_template, user_export_template, user_import_template, user_list_templates, user_load_template, user_rename_template,
+    user_save_template,
+};
+
+pub use cli::run_cli;
+use content_hash::CachedHash;
+pub use journal::{load_journal, record_batch, save_journal, undo_batch, Journal, JournalBatch, JournalEntry, UndoReport, UndoSkipped};
+pub use pipeline::Session;
+use process_tasks::PreviewCache;
+pub use template::{read_template, write_template, Template};
+
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+#[cfg(feature = "gui")]
+use std::sync::atomic::AtomicBool;
+#[cfg(feature = "gui")]
 use std::sync::Mutex;
+use std::time::SystemTime;
+#[cfg(feature = "gui")]
 use tauri::{Manager, State};
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct WorkingFile {
     source: PathBuf,
     target: PathBuf,
+    is_dir: bool,
     active: bool,
     stable_id: usize,
+    conflict: Conflict,
+    // blake3 of the content in hex, only set while something needs it
+    content_hash: Option<String>,
+    // files with the same content share a group, copy_index 0 is the first of them in the sort order
+    duplicate_group: Option<usize>,
+    copy_index: usize,
+    // read when the working files are built, None if the file couldn't be read
+    metadata: Option<FileMetadata>,
+}
+
+/// What the disk said about a file when it was loaded, so sorting, tasks and filters don't ask on every run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FileMetadata {
+    pub kind: FileKind,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub created: Option<SystemTime>,
+    pub accessed: Option<SystemTime>,
+    pub readonly: bool,
+    // the unix permission bits, None on other platforms
+    pub mode: Option<u32>,
+}
+
+impl From<&std::fs::Metadata> for FileMetadata {
+    fn from(metadata: &std::fs::Metadata) -> Self {
+        let kind = match (metadata.is_file(), metadata.is_dir()) {
+            (true, _) => FileKind::File,
+            (_, true) => FileKind::Directory,
+            _ => FileKind::Other,
+        };
+        #[cfg(unix)]
+        let mode = Some(std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()));
+        #[cfg(not(unix))]
+        let mode = None;
+
+        FileMetadata {
+            kind,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            created: metadata.created().ok(),
+            accessed: metadata.accessed().ok(),
+            readonly: metadata.permissions().readonly(),
+            mode,
+        }
+    }
+}
+
+impl FileMetadata {
+    /// Whether the file on disk is still the one that was loaded. The accessed time is left out, reading the file changes it.
+    pub fn matches(&self, current: &FileMetadata) -> bool {
+        FileMetadata { accessed: None, ..*self } == FileMetadata { accessed: None, ..*current }
+    }
+}
+
+/// What a loaded path is, links are followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FileKind {
+    File,
+    Directory,
+    Other,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct FileStatus {
-    old_file_name: String,
-    new_file_name: String,
-    active: bool,
-    selected: bool,
-    stable_id: usize,
+    pub old_file_name: String,
+    pub new_file_name: String,
+    pub is_dir: bool,
+    pub active: bool,
+    pub selected: bool,
+    pub stable_id: usize,
+    pub conflict: Conflict,
+    pub duplicate_group: Option<usize>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Default)]
 pub struct FileStatusStats {
     pub total: usize,
+    // rows the search lets through, the table scrolls over this many
+    pub visible: usize,
     pub selected: usize,
     pub filtered: usize,
     pub ready: usize,
+    pub conflicts: usize,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TaskError {
+    pub task_index: usize,
+    pub message: String,
 }
 
+/// The rows of the window the table last asked for, `offset` is the visible index of the first one.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct FileStatusResponse {
+    pub offset: usize,
     pub statuses: Vec<FileStatus>,
     pub stats: FileStatusStats,
+    pub task_errors: Vec<TaskError>,
+}
+
+/// The rows whose selection a click changed, by stable_id. The table patches the rows it has instead of fetching them again.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SelectionDelta {
+    pub selected: Vec<usize>,
+    pub deselected: Vec<usize>,
+    pub stats: FileStatusStats,
+}
+
+/// Which visible rows the responses carry. The table asks for the rows it scrolled to, every response after that has them.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct StatusWindow {
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Default for StatusWindow {
+    fn default() -> Self {
+        StatusWindow { offset: 0, limit: 200 }
+    }
+}
+
+/// How one file of an executed batch went. Skipped files were left alone because of a conflict.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FileOutcome {
+    Ok,
+    Skipped { reason: String },
+    Failed { kind: String, message: String },
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FileReport {
+    pub source: String,
+    pub target: String,
+    pub outcome: FileOutcome,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Default)]
+pub struct ExecutionReport {
+    // set when the batch was stopped before all files were written
+    pub cancelled: bool,
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub files: Vec<FileReport>,
+}
+
+/// Sent while a batch runs. Bytes only count up for moves that have to copy to another filesystem.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ExecutionProgress {
+    pub file: String,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub files_failed: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// The files that are still loaded, plus the report if the batch actually ran.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RenameResponse {
+    pub files: FileStatusResponse,
+    pub report: Option<ExecutionReport>,
+}
+
+/// The batch that is being written on a worker thread. Kept apart from the AppState,
+/// whose lock is only taken to start and finish the batch.
+#[cfg(feature = "gui")]
+#[derive(Debug, Default)]
+pub struct BatchControl {
+    running: AtomicBool,
+    cancel: AtomicBool,
+}
+
+/// An undo holds the batch slot too, it reads and writes the same files and journal as a batch.
+#[cfg(feature = "gui")]
+const BATCH_RUNNING: &str = "A batch is already running, wait for it to finish or cancel it first";
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SelectedFileStatusAnchor {
     stable_id: usize,
@@ -58,37 +254,66 @@ pub struct SelectedFileStatusAnchor {
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Default)]
 pub struct AppState {
     file_names: Vec<String>,
+    // read once per loaded file, until the user refreshes it or the file is unloaded
+    metadata: HashMap<PathBuf, FileMetadata>,
+    // folders the user opened, path matching filters see paths relative to them
+    folders: Vec<PathBuf>,
+    folder_options: FolderOptions,
     file_names_sorted: Vec<String>,
     working_files: Vec<WorkingFile>,
     tasks: Vec<Task>,
+    task_errors: Vec<TaskError>,
+    preview_cache: PreviewCache,
     sort_choice: String,
     sort_ascending: bool,
+    sort_options: SortOptions,
     search: String,
     output: Output,
+    conflict_policy: ConflictPolicy,
+    move_verification: MoveVerification,
+    include_directories: bool,
+    find_duplicates: bool,
+    hash_cache: HashMap<PathBuf, CachedHash>,
     file_statuses: Vec<FileStatus>,
     filtered_filestatus_indices: Option<Vec<usize>>,
+    status_window: StatusWindow,
     filtered_count: usize,
+    conflict_count: usize,
     selected_filestatuses: Option<HashSet<usize>>,
     selected_filestatus_anchor: Option<SelectedFileStatusAnchor>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-enum Task {
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Task {
     CustomText {
         text: String,
         at_start: bool,
+        #[serde(default)]
+        condition: Option<Box<Task>>,
         active: bool,
     },
     FindAndReplace {
         find_text: String,
         replace_text: String,
+        #[serde(default)]
+        use_regex: bool,
+        #[serde(default)]
+        case_insensitive: bool,
+        #[serde(default)]
+        first_match_only: bool,
+        #[serde(default)]
+        condition: Option<Box<Task>>,
         active: bool,
     },
     ClearAll {
+        #[serde(default)]
+        condition: Option<Box<Task>>,
         active: bool,
     },
     ChangeCase {
         case_choice: u8,
+        #[serde(default)]
+        condition: Option<Box<Task>>,
         active: bool,
     },
     NumSequence {
@@ -96,6 +321,16 @@ enum Task {
         num_padding: u64,
         at_start: bool,
         separator: String,
+        #[serde(default = "default_step")]
+        step: u64,
+        #[serde(default)]
+        numerals: Numerals,
+        #[serde(default)]
+        active_only: bool,
+        #[serde(default)]
+        per_folder: bool,
+        #[serde(default)]
+        condition: Option<Box<Task>>,
         active: bool,
     },
     Date {
@@ -104,20 +339,57 @@ enum Task {
         day: bool,
         at_start: bool,
         separator: String,
+        #[serde(default)]
+        date_sources: Vec<DateSource>,
+        #[serde(default)]
+        condition: Option<Box<Task>>,
         active: bool,
     },
     Time {
         at_start: bool,
         separator: String,
+        #[serde(default)]
+        date_sources: Vec<DateSource>,
+        #[serde(default)]
+        condition: Option<Box<Task>>,
+        active: bool,
+    },
+    AudioTag {
+        field: AudioTagField,
+        at_start: bool,
+        separator: String,
+        padding: u64,
+        fallback: String,
+        #[serde(default)]
+        condition: Option<Box<Task>>,
+        active: bool,
+    },
+    Template {
+        template: String,
+        #[serde(default)]
+        pattern: String,
+        start_num: u64,
+        #[serde(default)]
+        condition: Option<Box<Task>>,
         active: bool,
     },
     FilterName {
         inclusive: bool,
         name: String,
+        #[serde(default)]
+        name_target: NameTarget,
+        #[serde(default)]
+        match_mode: MatchMode,
+        #[serde(default)]
+        case_insensitive: bool,
+        #[serde(default)]
+        match_path: bool,
     },
     FilterDocType {
         inclusive: bool,
         doc_types: Vec<String>,
+        #[serde(default)]
+        name_target: NameTarget,
     },
     FilterTimePeriod {
         inclusive: bool,
@@ -133,6 +405,168 @@ enum Task {
         byte_base_size: u64,
         size: u64,
     },
+    FilterKind {
+        kind: EntryKind,
+    },
+    FilterGroup {
+        operator: GroupOperator,
+        filters: Vec<Task>,
+    },
+    FilterDuplicates {
+        keep: DuplicateKeep,
+    },
+}
+
+fn default_step() -> u64 {
+    1
+}
+
+/// How a NumSequence task writes its numbers. Letters and roman numerals start at 1 (a, i),
+/// numbers they can't show, like 0, are written as digits instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+pub enum Numerals {
+    #[default]
+    Decimal,
+    LowercaseLetters,
+    UppercaseLetters,
+    LowercaseRoman,
+    UppercaseRoman,
+}
+
+/// Where the Date and Time tasks read the date from. Tried in order, the first one the file has wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DateSource {
+    Modified,
+    Created,
+    Accessed,
+    ExifCaptured,
+}
+
+/// Which tag an AudioTag task reads from ID3, Vorbis comment, MP4 or APE tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum AudioTagField {
+    Artist,
+    AlbumArtist,
+    Album,
+    Title,
+    Track,
+    Disc,
+    Year,
+    Genre,
+}
+
+/// How a FilterName task compares its text. Glob `*` also matches across folders, so `*/thumbnails/*` works on paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize, Default)]
+pub enum MatchMode {
+    #[default]
+    Substring,
+    Exact,
+    Glob,
+    Regex,
+}
+
+/// How a FilterGroup combines the files its filters keep. None keeps the files no filter of the group keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+pub enum GroupOperator {
+    #[default]
+    All,
+    Any,
+    None,
+}
+
+/// Which name the FilterName and FilterDocType tasks look at. Original name filters run with the other
+/// filters before any transform, current name filters run at their place in the list on the name built so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+pub enum NameTarget {
+    #[default]
+    Original,
+    Current,
+}
+
+/// Which loaded entries a FilterKind task keeps. Folders are only loaded with include_directories on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+pub enum EntryKind {
+    #[default]
+    Both,
+    Files,
+    Directories,
+}
+
+/// Which files a FilterDuplicates task keeps, copies are found by their content. First keeps the files
+/// without copies and the first copy in the sort order, Copies keeps all the other ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+pub enum DuplicateKeep {
+    #[default]
+    First,
+    Unique,
+    Copies,
+}
+
+/// How names are compared when the files are sorted, in the order of the user's locale.
+/// Numbering tasks count in the sort order, so ties are broken by the `then_by` keys and at last by the full path.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SortOptions {
+    // digits are compared as numbers, so file2 comes before file10
+    pub natural: bool,
+    pub case_insensitive: bool,
+    // sort keys for files the sort choice ties on, like name after type. Always ascending
+    pub then_by: Vec<String>,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        SortOptions { natural: true, case_insensitive: true, then_by: Vec::new() }
+    }
+}
+
+/// How opened folders are walked. The defaults load everything inside them.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+pub struct FolderOptions {
+    // how many folders down to load, 1 is only what is directly inside, None has no limit
+    pub max_depth: Option<usize>,
+    pub skip_hidden: bool,
+    pub symlinks: Symlinks,
+    // skip what .gitignore, .ignore and .git/info/exclude files in and above the folder exclude
+    pub use_ignore_files: bool,
+}
+
+/// What a folder walk does with symbolic links. Keep loads the link itself without walking into a linked folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+pub enum Symlinks {
+    #[default]
+    Keep,
+    Follow,
+    Skip,
+}
+
+/// Why a file's new path can't simply be written. Checked against the disk before every run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+pub enum Conflict {
+    #[default]
+    None,
+    ExistsOnDisk,
+    CollidesWithSource,
+    InvalidName,
+    InsideMovingFolder,
+}
+
+/// What the executor does with conflicting files, chosen per batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    Skip,
+    AutoSuffix,
+    Overwrite,
+    Abort,
+}
+
+/// How a move onto another filesystem checks the copy before the original is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
+pub enum MoveVerification {
+    #[default]
+    Size,
+    Checksum,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Default)]
@@ -157,12 +591,25 @@ pub enum Output {
     },
 }
 
+impl Output {
+    /// Build an Output from the frontend dropdown code, anything unknown falls back to Replace.
+    pub fn from_choice(output_dropdown_choice: &str, output_directory: &str) -> Self {
+        match output_dropdown_choice {
+            "copy" => Output::Copy { directory: output_directory.to_string() },
+            "move" => Output::Move { directory: output_directory.to_string() },
+            _ => Output::Replace,
+        }
+    }
+}
+
 // App Entry Point
+#[cfg(feature = "gui")]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .setup(|app| {
             app.manage(Mutex::new(AppState::default()));
+            app.manage(BatchControl::default());
             Ok(())
         })
         .plugin(tauri_plugin_dialog::init())
@@ -172,11 +619,14 @@ pub fn run() {
             user_open_files,
             user_open_folders,
             user_update_sort,
+            user_update_sort_options,
             user_update_tasks,
             user_update_search,
             user_clear_files,
+            user_refresh_metadata,
             user_update_search,
             user_rename_files,
+            user_cancel_batch,
             user_notification,
             user_dialog,
             user_dragdrop_files,
@@ -185,6 +635,21 @@ pub fn run() {
             user_filestatus_shift_click,
             user_filestatus_selection_clear,
             user_filestatus_selection_delete,
+            user_filestatus_window,
+            user_update_output,
+            user_update_conflict_policy,
+            user_update_move_verification,
+            user_update_include_directories,
+            user_update_find_duplicates,
+            user_update_folder_options,
+            user_undo_last_batch,
+            user_save_template,
+            user_list_templates,
+            user_load_template,
+            user_delete_template,
+            user_rename_template,
+            user_import_template,
+            user_export_template,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,62 @@
+use crate::{MatchMode, Path, PathBuf};
+use glob::{MatchOptions, Pattern};
+use regex::{Regex, RegexBuilder};
+
+/// The text of a FilterName task, compiled once per run.
+#[derive(Debug)]
+pub enum NameMatcher {
+    Substring { text: String, case_insensitive: bool },
+    Exact { text: String, case_insensitive: bool },
+    Glob { pattern: Pattern, case_insensitive: bool },
+    Regex(Regex),
+}
+
+impl NameMatcher {
+    pub fn new(text: &str, match_mode: MatchMode, case_insensitive: bool) -> Result<NameMatcher, String> {
+        let folded = if case_insensitive { text.to_lowercase() } else { text.to_string() };
+
+        match match_mode {
+            MatchMode::Substring => Ok(NameMatcher::Substring { text: folded, case_insensitive }),
+            MatchMode::Exact => Ok(NameMatcher::Exact { text: folded, case_insensitive }),
+            MatchMode::Glob => Pattern::new(text).map(|pattern| NameMatcher::Glob { pattern, case_insensitive }).map_err(|e| e.to_string()),
+            MatchMode::Regex => {
+                RegexBuilder::new(text).case_insensitive(case_insensitive).build().map(NameMatcher::Regex).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let fold = |case_insensitive: bool| if case_insensitive { name.to_lowercase() } else { name.to_string() };
+
+        match self {
+            NameMatcher::Substring { text, case_insensitive } => fold(*case_insensitive).contains(text.as_str()),
+            NameMatcher::Exact { text, case_insensitive } => fold(*case_insensitive) == *text,
+            NameMatcher::Glob { pattern, case_insensitive } => {
+                let options = MatchOptions {
+                    case_sensitive: !case_insensitive,
+                    require_literal_separator: false,
+                    require_literal_leading_dot: false,
+                };
+                pattern.matches_with(name, options)
+            }
+            NameMatcher::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// The path of the file from the opened folder it was loaded from, that folder included, like `photos/thumbnails/a.jpg`.
+/// `name` is used as the last part so a current name filter sees the new name. Separators are always `/`.
+pub fn relative_path(source: &Path, name: &str, folders: &[PathBuf]) -> String {
+    let folder = folders.iter().filter(|folder| source.starts_with(folder)).max_by_key(|folder| folder.components().count());
+    let base = folder.map(|folder| folder.parent().unwrap_or(folder));
+
+    let mut parts: Vec<String> = match (base, source.parent()) {
+        (Some(base), Some(parent)) => parent
+            .strip_prefix(base)
+            .map(|relative| relative.components().map(|part| part.as_os_str().to_string_lossy().to_string()).collect())
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    parts.push(name.to_string());
+    parts.join("/")
+}
@@ -0,0 +1,225 @@
+use crate::atomics::replace_illegal_chars;
+use crate::process_tasks::{audio_tag_value, source_datetime};
+use crate::{AudioTagField, DateSource, WorkingFile};
+use regex::Regex;
+use time::format_description::{self, OwnedFormatItem};
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_HASH_LENGTH: usize = 8;
+
+/// A parsed Template task, like `{stem}_{n:03}_{date:%Y%m%d}{ext}`.
+/// Parsed once per run so a typo is reported on the task instead of on every file.
+#[derive(Debug)]
+pub struct NameTemplate {
+    segments: Vec<Segment>,
+    pattern: Option<Regex>,
+}
+
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    Token { token: Token, fallback: String },
+}
+
+#[derive(Debug)]
+enum Token {
+    Stem,
+    Extension,
+    Name,
+    Parent,
+    Counter { width: usize },
+    Date { source: DateSource, format: OwnedFormatItem },
+    Size { tier: u32 },
+    Hash { length: usize },
+    Audio { field: AudioTagField, padding: u64 },
+    Capture(String),
+}
+
+impl NameTemplate {
+    /// `pattern` is an optional regex run on the stem, its groups are the `{$1}` and `{$name}` tokens.
+    pub fn parse(template: &str, pattern: &str) -> Result<NameTemplate, String> {
+        let pattern = match pattern.is_empty() {
+            true => None,
+            false => Some(Regex::new(pattern).map_err(|e| e.to_string())?),
+        };
+
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut inner = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        inner.push(c);
+                    }
+                    if !closed {
+                        return Err(format!("the token {{{inner} is never closed, add a }}"));
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    let (token, fallback) = inner.split_once('|').unwrap_or((&inner, ""));
+                    check_literal(fallback)?;
+                    segments.push(Segment::Token { token: parse_token(token, pattern.as_ref())?, fallback: fallback.to_string() });
+                }
+                '}' => return Err("a } without a matching {, write }} for a literal brace".to_string()),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        for segment in &segments {
+            if let Segment::Literal(text) = segment {
+                check_literal(text)?;
+            }
+        }
+
+        Ok(NameTemplate { segments, pattern })
+    }
+
+    /// The new file name, built from the name the file has at this point of the task list.
+    /// Values a file doesn't have, like a missing tag, use the fallback after `|` or are left out.
+    /// Slashes and other characters a name can't hold are replaced in the values, a name of `.` or `..` is an error.
+    pub fn render(&self, file: &WorkingFile, counter: u64, current_name: &str) -> Result<String, String> {
+        // NOTE: folders keep dots in their names, they have no extension
+        let (stem, extension) = match current_name.rsplit_once('.') {
+            Some((stem, extension)) if !file.is_dir => (stem, format!(".{extension}")),
+            _ => (current_name, String::new()),
+        };
+        let captures = self.pattern.as_ref().and_then(|pattern| pattern.captures(stem));
+
+        let name: String = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => text.clone(),
+                Segment::Token { token, fallback } => {
+                    let value = match token {
+                        Token::Stem => Some(stem.to_string()),
+                        Token::Extension => Some(extension.clone()),
+                        Token::Name => Some(current_name.to_string()),
+                        Token::Parent => {
+                            file.source.parent().and_then(|parent| parent.file_name()).map(|name| name.to_string_lossy().to_string())
+                        }
+                        Token::Counter { width } => Some(format!("{counter:0width$}")),
+                        Token::Date { source, format } => {
+                            source_datetime(file, &[*source]).and_then(|datetime| datetime.format(format).ok())
+                        }
+                        Token::Size { tier } => {
+                            file.metadata.filter(|_| !file.is_dir).map(|metadata| (metadata.len / 1024_u64.pow(*tier)).to_string())
+                        }
+                        Token::Hash { length } => file.content_hash.as_ref().map(|hash| hash[..*length].to_string()),
+                        Token::Audio { field, padding } if !file.is_dir => audio_tag_value(&file.source, field, padding),
+                        Token::Audio { .. } => None,
+                        Token::Capture(group) => {
+                            let captures = captures.as_ref();
+                            let matched = match group.parse::<usize>() {
+                                Ok(index) => captures.and_then(|captures| captures.get(index)),
+                                Err(_) => captures.and_then(|captures| captures.name(group)),
+                            };
+                            matched.map(|matched| matched.as_str().to_string())
+                        }
+                    };
+                    value.map(|value| replace_illegal_chars(&value)).filter(|value| !value.is_empty()).unwrap_or_else(|| fallback.clone())
+                }
+            })
+            .collect();
+
+        match name.as_str() {
+            "." | ".." => Err(format!("the template names {} \"{name}\", that is not a file name", file.source.display())),
+            _ => Ok(name),
+        }
+    }
+}
+
+/// Text typed into the template can't split the name into folders.
+fn check_literal(text: &str) -> Result<(), String> {
+    match text.chars().find(|c| matches!(c, '/' | '\\')) {
+        Some(separator) => Err(format!("a {separator} would put the file in a folder, it can't be part of a name")),
+        None => Ok(()),
+    }
+}
+
+fn parse_token(token: &str, pattern: Option<&Regex>) -> Result<Token, String> {
+    let (name, spec) = match token.split_once(':') {
+        Some((name, spec)) => (name.trim(), Some(spec)),
+        None => (token.trim(), None),
+    };
+
+    let width = |default: usize| match spec {
+        None => Ok(default),
+        Some(spec) => spec.parse::<usize>().map_err(|_| format!("{{{name}:{spec}}} needs a number of digits, like {{{name}:03}}")),
+    };
+    let date = |source: DateSource| {
+        let format = format_description::parse_strftime_owned(spec.unwrap_or(DEFAULT_DATE_FORMAT))
+            .map_err(|e| format!("the date format in {{{token}}} is not valid: {e}"))?;
+        Ok::<Token, String>(Token::Date { source, format })
+    };
+    let audio = |field: AudioTagField| Ok::<Token, String>(Token::Audio { field, padding: width(0)? as u64 });
+
+    match name {
+        "stem" => Ok(Token::Stem),
+        "ext" => Ok(Token::Extension),
+        "name" => Ok(Token::Name),
+        "parent" => Ok(Token::Parent),
+        "n" => Ok(Token::Counter { width: width(1)? }),
+        "date" | "modified" => date(DateSource::Modified),
+        "created" => date(DateSource::Created),
+        "accessed" => date(DateSource::Accessed),
+        "taken" => date(DateSource::ExifCaptured),
+        "size" => {
+            let tier = match spec.map(str::to_uppercase).as_deref() {
+                None | Some("B") => 0,
+                Some("KB") => 1,
+                Some("MB") => 2,
+                Some("GB") => 3,
+                Some("TB") => 4,
+                Some(_) => return Err(format!("{{{token}}} can only be in B, KB, MB, GB or TB")),
+            };
+            Ok(Token::Size { tier })
+        }
+        "hash" => match width(DEFAULT_HASH_LENGTH)? {
+            length @ 1..=64 => Ok(Token::Hash { length }),
+            _ => Err(format!("{{{token}}} can be 1 to 64 characters long")),
+        },
+        "artist" => audio(AudioTagField::Artist),
+        "albumartist" => audio(AudioTagField::AlbumArtist),
+        "album" => audio(AudioTagField::Album),
+        "title" => audio(AudioTagField::Title),
+        "track" => audio(AudioTagField::Track),
+        "disc" => audio(AudioTagField::Disc),
+        "year" => audio(AudioTagField::Year),
+        "genre" => audio(AudioTagField::Genre),
+        _ if name.starts_with('$') => {
+            let group = &name[1..];
+            let Some(pattern) = pattern else {
+                return Err(format!("{{{name}}} needs a pattern to capture from"));
+            };
+            let exists = match group.parse::<usize>() {
+                Ok(index) => index < pattern.captures_len(),
+                Err(_) => pattern.capture_names().flatten().any(|capture_name| capture_name == group),
+            };
+            match exists {
+                true => Ok(Token::Capture(group.to_string())),
+                false => Err(format!("the pattern has no group {group}")),
+            }
+        }
+        _ => Err(format!("unknown token {{{name}}}")),
+    }
+}
@@ -0,0 +1,473 @@
+use crate::atomics::{
+    apply_search, apply_selections_to_filestatuses, build_response, convert_file_names_to_working_files,
+    convert_working_files_to_file_status, detect_workingfile_conflicts, expand_paths, resolve_workingfile_duplicates, snapshot_metadata,
+    solve_duplicates, sort_file_names, stale_files, state_clear_selected_filestatuses, state_update_search, state_update_sort,
+    state_update_tasks,
+};
+use crate::content_hash::hash_working_files;
+use crate::execute::execute_working_files;
+use crate::journal::JournalEntry;
+use crate::process_tasks::process_tasks_on_working_files;
+use crate::{
+    AppState, ConflictPolicy, ExecutionProgress, ExecutionReport, FileOutcome, FileStatus, FileStatusResponse, FolderOptions, HashSet,
+    MoveVerification, Output, Path, PathBuf, SortOptions, StatusWindow, Task, WorkingFile,
+};
+use std::sync::atomic::AtomicBool;
+
+// NOTE: each function below is one full pass of the pipeline on an already locked AppState.
+// The tauri commands lock the mutex once and call these, Session calls them on its own AppState.
+
+pub fn open_files(state: &mut AppState, file_names: Vec<String>) -> FileStatusResponse {
+    solve_duplicates(file_names, state);
+    rebuild_working_files(state)
+}
+
+/// Opens files and folders, folders are walked recursively and remembered for path matching.
+pub fn open_paths(state: &mut AppState, paths: Vec<String>) -> FileStatusResponse {
+    for path in &paths {
+        let path = PathBuf::from(path);
+        if path.is_dir() && !state.folders.contains(&path) {
+            state.folders.push(path);
+        }
+    }
+    let file_names = expand_paths(paths, &state.folder_options);
+    open_files(state, file_names)
+}
+
+/// The opened folders are walked again with the new options. Files opened on their own stay loaded.
+pub fn update_folder_options(state: &mut AppState, folder_options: FolderOptions) -> FileStatusResponse {
+    state.folder_options = folder_options;
+
+    let folders = state.folders.clone();
+    state.file_names.retain(|file_name| !folders.iter().any(|folder| Path::new(file_name).starts_with(folder)));
+    let folders = folders.iter().map(|folder| folder.to_string_lossy().to_string()).collect();
+    open_paths(state, folders)
+}
+
+pub fn update_sort(state: &mut AppState, sort_choice: String, sort_ascending: bool) -> FileStatusResponse {
+    state_update_sort(sort_choice, sort_ascending, state);
+    rebuild_working_files(state)
+}
+
+/// Natural, case and tie-breaking options of the sort, the files are sorted again.
+pub fn update_sort_options(state: &mut AppState, sort_options: SortOptions) -> FileStatusResponse {
+    state.sort_options = sort_options;
+    state_clear_selected_filestatuses(state);
+    rebuild_working_files(state)
+}
+
+pub fn update_tasks(state: &mut AppState, task_list: Vec<Task>) -> FileStatusResponse {
+    state_update_tasks(task_list, state);
+    rerun_tasks(state)
+}
+
+/// The output directory changes which paths conflict, so the targets are checked again.
+pub fn update_output(state: &mut AppState, output: Output) -> FileStatusResponse {
+    state.output = output;
+    rerun_tasks(state)
+}
+
+pub fn update_conflict_policy(state: &mut AppState, conflict_policy: ConflictPolicy) -> FileStatusResponse {
+    state.conflict_policy = conflict_policy;
+    rerun_tasks(state)
+}
+
+/// Folders are dropped or picked up when the working files are built, so everything runs again.
+pub fn update_include_directories(state: &mut AppState, include_directories: bool) -> FileStatusResponse {
+    state.include_directories = include_directories;
+    rebuild_working_files(state)
+}
+
+/// Files are hashed and grouped by content, or no longer hashed unless a task needs it.
+pub fn update_find_duplicates(state: &mut AppState, find_duplicates: bool) -> FileStatusResponse {
+    state.find_duplicates = find_duplicates;
+    rerun_tasks(state)
+}
+
+/// Only matters once the batch runs, the preview stays the same.
+pub fn update_move_verification(state: &mut AppState, move_verification: MoveVerification) {
+    state.move_verification = move_verification;
+}
+
+pub fn update_search(state: &mut AppState, search: String) -> FileStatusResponse {
+    state_update_search(search, state);
+    convert_working_files_to_file_status(state);
+    apply_selections_to_filestatuses(state);
+    apply_search(state);
+    build_response(state)
+}
+
+pub fn clear_files(state: &mut AppState) {
+    state.status_window.offset = 0;
+    state.file_names.clear();
+    state.metadata.clear();
+    state.folders.clear();
+    state.working_files.clear();
+    state.selected_filestatuses = None;
+    state.selected_filestatus_anchor = None;
+}
+
+/// The table scrolled, the rows it shows are sent and every response after this one carries the same window.
+pub fn update_status_window(state: &mut AppState, status_window: StatusWindow) -> FileStatusResponse {
+    state.status_window = status_window;
+    build_response(state)
+}
+
+/// Reads every loaded file from the disk again, for files that were changed outside the app since they were loaded.
+pub fn refresh_metadata(state: &mut AppState) -> FileStatusResponse {
+    state.metadata.clear();
+    rebuild_working_files(state)
+}
+
+/// Checks the current targets against the disk again, right before they get written.
+pub fn refresh_conflicts(state: &mut AppState) -> FileStatusResponse {
+    detect_workingfile_conflicts(state);
+    convert_working_files_to_file_status(state);
+    apply_selections_to_filestatuses(state);
+    apply_search(state);
+    build_response(state)
+}
+
+/// The current preview taken out of the AppState, so it can be written to disk without holding the state.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    working_files: Vec<WorkingFile>,
+    output: Output,
+    conflict_policy: ConflictPolicy,
+    move_verification: MoveVerification,
+}
+
+impl Batch {
+    /// Writes the batch to disk, `cancel` is checked between files. Fails without touching the disk if the conflict policy aborts.
+    pub fn run(
+        &mut self,
+        progress: &mut dyn FnMut(ExecutionProgress),
+        cancel: &AtomicBool,
+    ) -> Result<(ExecutionReport, Vec<JournalEntry>), String> {
+        execute_working_files(&mut self.working_files, &self.output, self.conflict_policy, self.move_verification, progress, cancel)
+    }
+}
+
+/// Fails if files changed on disk since they were loaded, their names may have been built from old sizes or dates.
+/// Those files are read again and the preview is rebuilt, so it can be checked before trying again.
+pub fn start_batch(state: &mut AppState) -> Result<Batch, String> {
+    let stale = stale_files(state);
+    if !stale.is_empty() {
+        for path in &stale {
+            state.metadata.remove(path);
+        }
+        rebuild_working_files(state);
+        return Err(format!(
+            "{} files changed on disk since they were loaded. The preview was updated, check it and rename again.",
+            stale.len()
+        ));
+    }
+
+    // the disk may have changed since the last preview, so conflicts are checked again first
+    refresh_conflicts(state);
+    Ok(Batch {
+        working_files: state.working_files.clone(),
+        output: state.output.clone(),
+        conflict_policy: state.conflict_policy,
+        move_verification: state.move_verification,
+    })
+}
+
+/// Files that made it to disk are unloaded, skipped and failed ones stay loaded so they can be fixed and tried again.
+pub fn finish_batch(state: &mut AppState, report: &ExecutionReport) -> FileStatusResponse {
+    let succeeded: HashSet<&str> =
+        report.files.iter().filter(|file| file.outcome == FileOutcome::Ok).map(|file| file.source.as_str()).collect();
+    state.file_names.retain(|file_name| !succeeded.contains(file_name.as_str()));
+    // NOTE: the batch changed the folders it took files out of and put files into, they are read again so they don't look stale
+    for file in &report.files {
+        for path in [&file.source, &file.target] {
+            if let Some(folder) = Path::new(path).parent() {
+                state.metadata.remove(folder);
+            }
+        }
+    }
+    rebuild_working_files(state)
+}
+
+/// Writes the current preview to disk in one go, see start_batch, Batch::run and finish_batch.
+pub fn execute(
+    state: &mut AppState,
+    progress: &mut dyn FnMut(ExecutionProgress),
+    cancel: &AtomicBool,
+) -> Result<(ExecutionReport, Vec<JournalEntry>), String> {
+    let mut batch = start_batch(state)?;
+    let (report, journal_entries) = batch.run(progress, cancel)?;
+    finish_batch(state, &report);
+    Ok((report, journal_entries))
+}
+
+/// Reads the metadata of newly loaded files, sorts the loaded file names and runs every stage after that.
+fn rebuild_working_files(state: &mut AppState) -> FileStatusResponse {
+    snapshot_metadata(state);
+    sort_file_names(state);
+    convert_file_names_to_working_files(state);
+    hash_working_files(state);
+    process_tasks_on_working_files(state);
+    resolve_workingfile_duplicates(state);
+    detect_workingfile_conflicts(state);
+    state_clear_selected_filestatuses(state);
+    convert_working_files_to_file_status(state);
+    apply_selections_to_filestatuses(state);
+    apply_search(state);
+    build_response(state)
+}
+
+/// Runs the task list on the loaded working files, keeping the sort order and selection.
+fn rerun_tasks(state: &mut AppState) -> FileStatusResponse {
+    // tasks that need the content hashes may have been added, files hashed before come from the cache
+    hash_working_files(state);
+    process_tasks_on_working_files(state);
+    resolve_workingfile_duplicates(state);
+    detect_workingfile_conflicts(state);
+    convert_working_files_to_file_status(state);
+    apply_selections_to_filestatuses(state);
+    apply_search(state);
+    build_response(state)
+}
+
+/// The renamer without the app window. Holds its own AppState and runs the same pipeline as the GUI.
+///
+/// ```no_run
+/// use filera_lib::{Output, Session};
+///
+/// let mut session = Session::new();
+/// session.open_paths(vec!["./photos".to_string()]);
+/// let preview = session.set_output(Output::Replace);
+/// let written = session.execute();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    state: AppState,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    pub fn open_files(&mut self, file_names: Vec<String>) -> FileStatusResponse {
+        open_files(&mut self.state, file_names)
+    }
+
+    /// Like open_files, but folders are walked recursively first.
+    pub fn open_paths(&mut self, paths: Vec<String>) -> FileStatusResponse {
+        open_paths(&mut self.state, paths)
+    }
+
+    pub fn set_sort(&mut self, sort_choice: &str, sort_ascending: bool) -> FileStatusResponse {
+        update_sort(&mut self.state, sort_choice.to_string(), sort_ascending)
+    }
+
+    pub fn set_sort_options(&mut self, sort_options: SortOptions) -> FileStatusResponse {
+        update_sort_options(&mut self.state, sort_options)
+    }
+
+    pub fn set_tasks(&mut self, tasks: Vec<Task>) -> FileStatusResponse {
+        update_tasks(&mut self.state, tasks)
+    }
+
+    pub fn set_search(&mut self, search: &str) -> FileStatusResponse {
+        update_search(&mut self.state, search.to_string())
+    }
+
+    pub fn set_output(&mut self, output: Output) -> FileStatusResponse {
+        update_output(&mut self.state, output)
+    }
+
+    pub fn set_conflict_policy(&mut self, conflict_policy: ConflictPolicy) -> FileStatusResponse {
+        update_conflict_policy(&mut self.state, conflict_policy)
+    }
+
+    pub fn set_include_directories(&mut self, include_directories: bool) -> FileStatusResponse {
+        update_include_directories(&mut self.state, include_directories)
+    }
+
+    /// Applies to folders opened before and after, set it first to never walk the skipped parts at all.
+    pub fn set_folder_options(&mut self, folder_options: FolderOptions) -> FileStatusResponse {
+        update_folder_options(&mut self.state, folder_options)
+    }
+
+    pub fn set_find_duplicates(&mut self, find_duplicates: bool) -> FileStatusResponse {
+        update_find_duplicates(&mut self.state, find_duplicates)
+    }
+
+    pub fn set_move_verification(&mut self, move_verification: MoveVerification) {
+        update_move_verification(&mut self.state, move_verification);
+    }
+
+    pub fn clear_files(&mut self) {
+        clear_files(&mut self.state);
+    }
+
+    pub fn set_status_window(&mut self, status_window: StatusWindow) -> FileStatusResponse {
+        update_status_window(&mut self.state, status_window)
+    }
+
+    pub fn refresh_metadata(&mut self) -> FileStatusResponse {
+        refresh_metadata(&mut self.state)
+    }
+
+    pub fn tasks(&self) -> &[Task] {
+        &self.state.tasks
+    }
+
+    pub fn file_statuses(&self) -> &[FileStatus] {
+        &self.state.file_statuses
+    }
+
+    pub fn response(&self) -> FileStatusResponse {
+        build_response(&self.state)
+    }
+
+    /// Writes the current preview to the output set with set_output, like the Batch Rename button does.
+    /// Only the files that made it to disk are unloaded.
+    pub fn execute(&mut self) -> Result<(ExecutionReport, Vec<JournalEntry>), String> {
+        execute(&mut self.state, &mut |_| {}, &AtomicBool::new(false))
+    }
+
+    /// Like execute, with `progress` called after every file and while moves to another filesystem copy.
+    pub fn execute_with_progress(
+        &mut self,
+        progress: &mut dyn FnMut(ExecutionProgress),
+    ) -> Result<(ExecutionReport, Vec<JournalEntry>), String> {
+        execute(&mut self.state, progress, &AtomicBool::new(false))
+    }
+
+    /// Like execute_with_progress, setting `cancel` from another thread stops the batch before the next file.
+    pub fn execute_cancellable(
+        &mut self,
+        progress: &mut dyn FnMut(ExecutionProgress),
+        cancel: &AtomicBool,
+    ) -> Result<(ExecutionReport, Vec<JournalEntry>), String> {
+        execute(&mut self.state, progress, cancel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use crate::Conflict;
+
+    fn open(session: &mut Session, paths: &[PathBuf]) {
+        session.open_files(paths.iter().map(|path| path.to_string_lossy().to_string()).collect());
+    }
+
+    #[test]
+    fn copies_with_the_same_name_from_different_folders_conflict() {
+        let dir = TestDir::new("same-destination");
+        let output = Output::Copy { directory: dir.folder("out").to_string_lossy().to_string() };
+        let mut session = Session::new();
+        open(&mut session, &[dir.file("a/x.jpg"), dir.file("b/x.jpg")]);
+        session.set_output(output);
+
+        let conflicts: Vec<Conflict> = session.state.working_files.iter().map(|file| file.conflict).collect();
+        assert_eq!(conflicts, [Conflict::None, Conflict::CollidesWithSource]);
+
+        let (report, journal) = session.execute().unwrap();
+        assert_eq!((report.succeeded, report.skipped), (1, 1));
+        assert_eq!(journal.len(), 1);
+        assert_eq!(dir.contents("out/x.jpg"), "a/x.jpg");
+    }
+
+    #[test]
+    fn same_name_gets_a_suffix_with_auto_suffix() {
+        let dir = TestDir::new("same-destination-suffix");
+        let output = Output::Move { directory: dir.folder("out").to_string_lossy().to_string() };
+        let mut session = Session::new();
+        open(&mut session, &[dir.file("a/x.jpg"), dir.file("b/x.jpg")]);
+        session.set_conflict_policy(ConflictPolicy::AutoSuffix);
+        session.set_output(output);
+
+        let (report, _) = session.execute().unwrap();
+        assert_eq!(report.succeeded, 2);
+        assert_eq!(dir.contents("out/x.jpg"), "a/x.jpg");
+        assert_eq!(dir.contents("out/x_0001.jpg"), "b/x.jpg");
+    }
+
+    #[test]
+    fn template_counter_skips_filtered_files() {
+        let dir = TestDir::new("template-counter");
+        let tasks: Vec<Task> = serde_json::from_value(serde_json::json!([
+            { "FilterName": { "inclusive": true, "name": "b" } },
+            { "Template": { "template": "{n:02}{ext}", "start_num": 1, "active": true } }
+        ]))
+        .unwrap();
+        let mut session = Session::new();
+        open(&mut session, &[dir.file("a.txt"), dir.file("b.txt"), dir.file("c.txt")]);
+        session.set_sort("name", true);
+        session.set_tasks(tasks);
+
+        let names: Vec<&str> =
+            session.file_statuses().iter().filter(|status| status.active).map(|status| status.new_file_name.as_str()).collect();
+        assert_eq!(names, ["01.txt", "02.txt"]);
+    }
+
+    #[test]
+    fn folder_is_not_stale_after_its_files_were_renamed() {
+        let dir = TestDir::new("stale-folder");
+        dir.file("f/x.txt");
+        let tasks = |json: serde_json::Value| serde_json::from_value::<Vec<Task>>(json).unwrap();
+        let mut session = Session::new();
+        session.set_include_directories(true);
+        session.set_tasks(tasks(serde_json::json!([
+            { "FilterKind": { "kind": "Files" } },
+            { "FindAndReplace": { "find_text": "x.txt", "replace_text": "y.txt", "active": true } }
+        ])));
+        session.open_paths(vec![dir.path("f").to_string_lossy().to_string()]);
+
+        let (report, _) = session.execute().unwrap();
+        assert_eq!(report.succeeded, 1);
+        assert_eq!(dir.listing(), ["f", "f/y.txt"]);
+
+        // the folder was filtered out and stays loaded, the batch changed its modified time
+        session.set_tasks(tasks(serde_json::json!([
+            { "FindAndReplace": { "find_text": "f", "replace_text": "g", "active": true } }
+        ])));
+        let (report, _) = session.execute().unwrap();
+        assert_eq!(report.succeeded, 1);
+        assert_eq!(dir.listing(), ["g", "g/y.txt"]);
+    }
+
+    #[test]
+    fn names_that_leave_the_folder_are_invalid() {
+        let dir = TestDir::new("leave-folder");
+        let find_and_replace = |find: &str, replace: &str| {
+            serde_json::from_value::<Task>(serde_json::json!({
+                "FindAndReplace": { "find_text": find, "replace_text": replace, "active": true }
+            }))
+            .unwrap()
+        };
+        let mut tasks = vec![find_and_replace("a.jpg", "../x.jpg"), find_and_replace("b.jpg", "..")];
+        let mut session = Session::new();
+        open(&mut session, &[dir.file("sub/a.jpg"), dir.file("sub/b.jpg")]);
+        session.set_sort("name", true);
+        session.set_tasks(tasks.clone());
+
+        let names = |session: &Session| -> Vec<(String, Conflict)> {
+            session.file_statuses().iter().map(|status| (status.new_file_name.clone(), status.conflict)).collect()
+        };
+        let expected = [("../x.jpg".to_string(), Conflict::InvalidName), ("..".to_string(), Conflict::InvalidName)];
+        assert_eq!(names(&session), expected);
+
+        // the first two tasks come from the preview cache this time, the result has to match a fresh run
+        tasks.push(find_and_replace("jpg", "png"));
+        session.set_tasks(tasks.clone());
+        let mut fresh = Session::new();
+        open(&mut fresh, &[dir.path("sub/a.jpg"), dir.path("sub/b.jpg")]);
+        fresh.set_sort("name", true);
+        fresh.set_tasks(tasks);
+        assert_eq!(names(&session), names(&fresh));
+        assert!(names(&session).iter().all(|(_, conflict)| *conflict == Conflict::InvalidName));
+
+        let (report, journal) = session.execute().unwrap();
+        assert_eq!((report.succeeded, report.skipped), (0, 2));
+        assert!(journal.is_empty());
+        assert_eq!(dir.listing(), ["sub", "sub/a.jpg", "sub/b.jpg"]);
+    }
+}
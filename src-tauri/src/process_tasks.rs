@@ -1,14 +1,83 @@
-use crate::{AppState, Mutex, State, Task, WorkingFile};
+use crate::atomics::{replace_illegal_chars, target_name};
+use crate::name_matcher::{relative_path, NameMatcher};
+use crate::name_template::NameTemplate;
+use crate::{
+    AppState, AudioTagField, DateSource, DuplicateKeep, EntryKind, GroupOperator, HashMap, MatchMode, NameTarget, Numerals, Path, PathBuf,
+    Task, TaskError, WorkingFile,
+};
+use lofty::prelude::{Accessor, ItemKey, TaggedFileExt};
+use regex::{NoExpand, Regex, RegexBuilder};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Mutex;
 use time::format_description::well_known::Iso8601;
-use time::OffsetDateTime;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
 
 const BLANK_NAME: &str = "<BLANK>";
 
+/// The name a file had and whether it was still active after one task.
+/// Tasks only change the file name, so the name is kept relative to the folder of the source.
+/// A name with a separator or `..` in it is kept whole, so the cached target is the one the tasks built.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Checkpoint {
+    name: OsString,
+    active: bool,
+}
+
+/// The files after each task of the last run, in the order the tasks ran.
+/// A change to the tasks only reruns them from the first one that differs.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Default)]
+pub struct PreviewCache {
+    sources: Vec<PathBuf>,
+    tasks: Vec<Task>,
+    checkpoints: Vec<Vec<Checkpoint>>,
+    // the first file each task couldn't name, reported again when the checkpoint is reused
+    failures: Vec<Option<TaskError>>,
+}
+
+/// DateTimeOriginal is the camera clock when the shot was taken. It has no time zone,
+/// so it is kept as is unless OffsetTimeOriginal says otherwise, the digits in the name match the camera.
+fn exif_capture_datetime(path: &Path) -> Option<OffsetDateTime> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+
+    let ascii_field = |tag: exif::Tag| match exif.get_field(tag, exif::In::PRIMARY).map(|field| &field.value) {
+        Some(exif::Value::Ascii(values)) => values.first().cloned(),
+        _ => None,
+    };
+
+    let mut captured = exif::DateTime::from_ascii(&ascii_field(exif::Tag::DateTimeOriginal)?).ok()?;
+    if let Some(offset) = ascii_field(exif::Tag::OffsetTimeOriginal) {
+        let _ = captured.parse_offset(&offset);
+    }
+
+    let date = Date::from_calendar_date(captured.year.into(), Month::try_from(captured.month).ok()?, captured.day).ok()?;
+    let time = Time::from_hms(captured.hour, captured.minute, captured.second).ok()?;
+    let offset = UtcOffset::from_whole_seconds(i32::from(captured.offset.unwrap_or(0)) * 60).ok()?;
+
+    Some(PrimitiveDateTime::new(date, time).assume_offset(offset))
+}
+
+/// The first date source the file has. No sources means the modified date, like before sources existed.
+pub(crate) fn source_datetime(file: &WorkingFile, date_sources: &[DateSource]) -> Option<OffsetDateTime> {
+    let date_sources = if date_sources.is_empty() { &[DateSource::Modified][..] } else { date_sources };
+    let metadata = file.metadata;
+
+    date_sources.iter().find_map(|date_source| match date_source {
+        DateSource::Modified => metadata?.modified.map(OffsetDateTime::from),
+        DateSource::Created => metadata?.created.map(OffsetDateTime::from),
+        DateSource::Accessed => metadata?.accessed.map(OffsetDateTime::from),
+        DateSource::ExifCaptured if !file.is_dir => exif_capture_datetime(&file.source),
+        DateSource::ExifCaptured => None,
+    })
+}
+
 fn task_custom_text(file: &mut WorkingFile, text: &String, at_start: &bool, active: &bool) {
     if *active {
         let file_stem: String;
         let file_extension: String;
-        let file_name = file.target.file_name().unwrap().to_string_lossy();
+        let file_name = file.target.file_name().unwrap_or_default().to_string_lossy();
         let start_period: bool = file_name.starts_with(".");
         let has_period: bool = file_name.contains(".");
 
@@ -43,7 +112,29 @@ fn task_custom_text(file: &mut WorkingFile, text: &String, at_start: &bool, acti
         };
     }
 }
-fn task_find_and_replace(file: &mut WorkingFile, find_text: &str, replace_text: &str, active: &bool) {
+fn compile_find_and_replace(
+    find_text: &str,
+    use_regex: &bool,
+    case_insensitive: &bool,
+    first_match_only: &bool,
+) -> Result<Option<Regex>, regex::Error> {
+    // NOTE: plain literal replacement keeps using str::replace, no need for a regex
+    if !*use_regex && !*case_insensitive && !*first_match_only {
+        return Ok(None);
+    }
+
+    let pattern = if *use_regex { find_text.to_string() } else { regex::escape(find_text) };
+    RegexBuilder::new(&pattern).case_insensitive(*case_insensitive).build().map(Some)
+}
+fn task_find_and_replace(
+    file: &mut WorkingFile,
+    find_text: &str,
+    replace_text: &str,
+    find_regex: Option<&Regex>,
+    use_regex: &bool,
+    first_match_only: &bool,
+    active: &bool,
+) {
     if *active {
         let mut new_file_name: String;
 
@@ -51,9 +142,17 @@ fn task_find_and_replace(file: &mut WorkingFile, find_text: &str, replace_text:
 
         if let Some(t) = target_file_name {
             let temp_val = t.to_string_lossy().to_string();
+            let limit = if *first_match_only { 1 } else { 0 };
 
             if temp_val == BLANK_NAME {
                 new_file_name = BLANK_NAME.to_string();
+            } else if let Some(find_regex) = find_regex {
+                // capture groups like $1 or ${name} are only expanded in regex mode
+                new_file_name = if *use_regex {
+                    find_regex.replacen(&temp_val, limit, replace_text).to_string()
+                } else {
+                    find_regex.replacen(&temp_val, limit, NoExpand(replace_text)).to_string()
+                };
             } else {
                 new_file_name = temp_val.replace(find_text, replace_text);
             }
@@ -103,21 +202,68 @@ fn task_change_case(file: &mut WorkingFile, case_choice: &u8, active: &bool) {
         }
     }
 }
+/// a, b, .. z, aa, ab like spreadsheet columns, 1 is a.
+fn letters(mut num: u64) -> String {
+    let mut letters: Vec<u8> = Vec::new();
+    while num > 0 {
+        num -= 1;
+        letters.push(b'a' + (num % 26) as u8);
+        num /= 26;
+    }
+    letters.iter().rev().map(|&letter| letter as char).collect()
+}
+fn roman(mut num: u64) -> String {
+    const NUMERALS: [(u64, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut roman = String::new();
+    for (value, numeral) in NUMERALS {
+        while num >= value {
+            roman.push_str(numeral);
+            num -= value;
+        }
+    }
+    roman
+}
+fn format_sequence_num(num: u64, num_padding: usize, numerals: &Numerals) -> String {
+    match numerals {
+        Numerals::LowercaseLetters if num > 0 => letters(num),
+        Numerals::UppercaseLetters if num > 0 => letters(num).to_uppercase(),
+        // NOTE: past 3999 roman numerals need overlines, which file names can't have
+        Numerals::LowercaseRoman if (1..4000).contains(&num) => roman(num),
+        Numerals::UppercaseRoman if (1..4000).contains(&num) => roman(num).to_uppercase(),
+        _ => format!("{num:0num_padding$}"),
+    }
+}
+#[allow(clippy::too_many_arguments)]
 fn task_num_sequence(
     file: &mut WorkingFile,
-    index: usize,
+    position: u64,
     start_num: &u64,
     num_padding: &u64,
     at_start: &bool,
     separator: &String,
+    step: &u64,
+    numerals: &Numerals,
     active: &bool,
-) {
+) -> Result<(), String> {
     if *active {
         let file_stem: String;
         let file_extension: String;
-        let index_u64 = u64::try_from(index).unwrap();
         let num_padding_usize: usize = usize::try_from(*num_padding).unwrap();
-        let file_name = file.target.file_name().unwrap().to_string_lossy();
+        let file_name = file.target.file_name().unwrap_or_default().to_string_lossy();
 
         if file_name == BLANK_NAME {
             file_stem = "".to_string();
@@ -127,8 +273,10 @@ fn task_num_sequence(
             file_stem = "".to_string();
         }
 
-        let raw_sequence_num = index_u64 + start_num;
-        let sequence_num = format!("{raw_sequence_num:0num_padding_usize$}");
+        let Some(raw_sequence_num) = position.checked_mul(*step).and_then(|offset| start_num.checked_add(offset)) else {
+            return Err(format!("the sequence goes past {}, lower the start number or the step", u64::MAX));
+        };
+        let sequence_num = format_sequence_num(raw_sequence_num, num_padding_usize, numerals);
 
         if file_stem.is_empty() {
             file.target.set_file_name(&sequence_num);
@@ -148,147 +296,221 @@ fn task_num_sequence(
             }
         }
     }
+    Ok(())
 }
-fn task_date(file: &mut WorkingFile, year: &u8, month: &bool, day: &bool, at_start: &bool, separator: &String, active: &bool) {
+#[allow(clippy::too_many_arguments)]
+fn task_date(
+    file: &mut WorkingFile,
+    year: &u8,
+    month: &bool,
+    day: &bool,
+    at_start: &bool,
+    separator: &String,
+    date_sources: &[DateSource],
+    active: &bool,
+) {
     if *active {
-        let file_metadata = file.source.metadata();
-
-        if let Ok(t) = file_metadata {
-            let systime = t.modified();
+        if let Some(datetime) = source_datetime(file, date_sources) {
+            let mut dates_combined_vector: Vec<String> = vec![];
 
-            if let Ok(j) = systime {
-                let datetime = OffsetDateTime::from(j);
-                let mut dates_combined_vector: Vec<String> = vec![];
-
-                let year_val = datetime.year().to_string();
-                match *year {
-                    0 => {
-                        dates_combined_vector.push(year_val);
-                    }
-                    1 => {
-                        dates_combined_vector.push(year_val.chars().take(year_val.len().saturating_sub(2)).collect());
-                    }
-                    _ => {}
-                };
-
-                if *month {
-                    let month_val = datetime.month() as u8;
-                    dates_combined_vector.push(format!("{month_val:02}"));
+            let year_val = datetime.year().to_string();
+            match *year {
+                0 => {
+                    dates_combined_vector.push(year_val);
                 }
-                if *day {
-                    let day_val = datetime.day();
-                    dates_combined_vector.push(format!("{day_val:02}"));
+                1 => {
+                    dates_combined_vector.push(year_val.chars().take(year_val.len().saturating_sub(2)).collect());
                 }
-                let dates_formatted_string: String = dates_combined_vector.join(separator);
+                _ => {}
+            };
 
-                let file_stem: String;
-                let file_extension: String;
-                let file_name = file.target.file_name().unwrap().to_string_lossy();
+            if *month {
+                let month_val = datetime.month() as u8;
+                dates_combined_vector.push(format!("{month_val:02}"));
+            }
+            if *day {
+                let day_val = datetime.day();
+                dates_combined_vector.push(format!("{day_val:02}"));
+            }
+            let dates_formatted_string: String = dates_combined_vector.join(separator);
 
-                if file_name == BLANK_NAME {
-                    file_stem = "".to_string();
-                } else if let Some(t) = file.target.file_stem() {
-                    file_stem = t.to_string_lossy().to_string();
-                } else {
-                    file_stem = "".to_string();
-                }
+            let file_stem: String;
+            let file_extension: String;
+            let file_name = file.target.file_name().unwrap_or_default().to_string_lossy();
 
-                if file_stem.is_empty() {
-                    file.target.set_file_name(&dates_formatted_string);
-                } else if *at_start {
-                    if let Some(t) = file.target.extension() {
-                        file_extension = t.to_string_lossy().to_string();
-                        file.target.set_file_name(format!("{dates_formatted_string}{separator}{file_stem}.{file_extension}"));
-                    } else {
-                        file.target.set_file_name(format!("{dates_formatted_string}{separator}{file_stem}"));
-                    }
+            if file_name == BLANK_NAME {
+                file_stem = "".to_string();
+            } else if let Some(t) = file.target.file_stem() {
+                file_stem = t.to_string_lossy().to_string();
+            } else {
+                file_stem = "".to_string();
+            }
+
+            if file_stem.is_empty() {
+                file.target.set_file_name(&dates_formatted_string);
+            } else if *at_start {
+                if let Some(t) = file.target.extension() {
+                    file_extension = t.to_string_lossy().to_string();
+                    file.target.set_file_name(format!("{dates_formatted_string}{separator}{file_stem}.{file_extension}"));
                 } else {
-                    if let Some(t) = file.target.extension() {
-                        file_extension = t.to_string_lossy().to_string();
-                        file.target.set_file_name(format!("{file_stem}{separator}{dates_formatted_string}.{file_extension}"));
-                    } else {
-                        file.target.set_file_name(format!("{file_stem}{separator}{dates_formatted_string}"));
-                    }
+                    file.target.set_file_name(format!("{dates_formatted_string}{separator}{file_stem}"));
                 }
             } else {
-                // able to access metadata but not file modified info
-                // i guess we just skip this step altogether for now
-                // should probably bubble up an error to the user
+                if let Some(t) = file.target.extension() {
+                    file_extension = t.to_string_lossy().to_string();
+                    file.target.set_file_name(format!("{file_stem}{separator}{dates_formatted_string}.{file_extension}"));
+                } else {
+                    file.target.set_file_name(format!("{file_stem}{separator}{dates_formatted_string}"));
+                }
             }
         } else {
-            // This means we can't access metadata on file at all
+            // none of the date sources are available for this file
             // i guess we just skip this step altogether for now
             // should probably bubble up an error to the user
         }
     }
 }
-fn task_time(file: &mut WorkingFile, at_start: &bool, separator: &String, active: &bool) {
+fn task_time(file: &mut WorkingFile, at_start: &bool, separator: &String, date_sources: &[DateSource], active: &bool) {
     if *active {
-        let file_metadata = file.source.metadata();
-
-        if let Ok(t) = file_metadata {
-            let systime = t.modified();
-
-            if let Ok(j) = systime {
-                let datetime = OffsetDateTime::from(j);
-                let datetime_hour = format!("{:02}", datetime.hour());
-                let datetime_minute = format!("{:02}", datetime.minute());
-                let datetime_second = format!("{:02}", datetime.second());
-
-                let file_stem: String;
-                let file_extension: String;
-                let file_name = file.target.file_name().unwrap().to_string_lossy();
+        if let Some(datetime) = source_datetime(file, date_sources) {
+            let datetime_hour = format!("{:02}", datetime.hour());
+            let datetime_minute = format!("{:02}", datetime.minute());
+            let datetime_second = format!("{:02}", datetime.second());
+
+            let file_stem: String;
+            let file_extension: String;
+            let file_name = file.target.file_name().unwrap_or_default().to_string_lossy();
+
+            if file_name == BLANK_NAME {
+                file_stem = "".to_string();
+            } else if let Some(t) = file.target.file_stem() {
+                file_stem = t.to_string_lossy().to_string();
+            } else {
+                file_stem = "".to_string();
+            }
 
-                if file_name == BLANK_NAME {
-                    file_stem = "".to_string();
-                } else if let Some(t) = file.target.file_stem() {
-                    file_stem = t.to_string_lossy().to_string();
+            if file_stem.is_empty() {
+                file.target.set_file_name(format!("{datetime_hour}{separator}{datetime_minute}{separator}{datetime_second}"));
+            } else if *at_start {
+                if let Some(t) = file.target.extension() {
+                    file_extension = t.to_string_lossy().to_string();
+                    file.target.set_file_name(format!(
+                        "{datetime_hour}{separator}{datetime_minute}{separator}{datetime_second}{separator}{file_stem}.{file_extension}"
+                    ));
                 } else {
-                    file_stem = "".to_string();
+                    file.target.set_file_name(format!(
+                        "{datetime_hour}{separator}{datetime_minute}{separator}{datetime_second}{separator}{file_stem}"
+                    ));
                 }
-
-                if file_stem.is_empty() {
-                    file.target.set_file_name(format!("{datetime_hour}{separator}{datetime_minute}{separator}{datetime_second}"));
-                } else if *at_start {
-                    if let Some(t) = file.target.extension() {
-                        file_extension = t.to_string_lossy().to_string();
-                        file.target.set_file_name(format!(
-                                            "{datetime_hour}{separator}{datetime_minute}{separator}{datetime_second}{separator}{file_stem}.{file_extension}"
-                                        ));
-                    } else {
-                        file.target.set_file_name(format!(
-                            "{datetime_hour}{separator}{datetime_minute}{separator}{datetime_second}{separator}{file_stem}"
-                        ));
-                    }
+            } else {
+                if let Some(t) = file.target.extension() {
+                    file_extension = t.to_string_lossy().to_string();
+                    file.target.set_file_name(format!(
+                        "{file_stem}{separator}{datetime_hour}{separator}{datetime_minute}{separator}{datetime_second}.{file_extension}"
+                    ));
                 } else {
-                    if let Some(t) = file.target.extension() {
-                        file_extension = t.to_string_lossy().to_string();
-                        file.target.set_file_name(format!(
-                                            "{file_stem}{separator}{datetime_hour}{separator}{datetime_minute}{separator}{datetime_second}.{file_extension}"
-                                        ));
-                    } else {
-                        file.target.set_file_name(format!(
-                            "{file_stem}{separator}{datetime_hour}{separator}{datetime_minute}{separator}{datetime_second}"
-                        ));
-                    }
+                    file.target.set_file_name(format!(
+                        "{file_stem}{separator}{datetime_hour}{separator}{datetime_minute}{separator}{datetime_second}"
+                    ));
                 }
-            } else {
-                // able to access metadata but not file modified info
-                // i guess we just skip this step altogether for now
-                // should probably bubble up an error to the user
             }
         } else {
-            // This means we can't access metadata on file at all
+            // none of the date sources are available for this file
             // i guess we just skip this step altogether for now
             // should probably bubble up an error to the user
         }
     }
 }
-fn task_filter_name(file: &mut WorkingFile, inclusive: &bool, name: &str) {
-    if !name.is_empty() {
+/// Reads one field from the audio tags of the file, the primary tag of the format first.
+/// Track and disc numbers are zero padded to `padding` digits.
+pub(crate) fn audio_tag_value(path: &Path, field: &AudioTagField, padding: &u64) -> Option<String> {
+    let tagged_file = lofty::read_from_path(path).ok()?;
+    let tag = tagged_file.primary_tag().or(tagged_file.first_tag())?;
+    let padding = *padding as usize;
+
+    let value = match field {
+        AudioTagField::Artist => tag.artist()?.to_string(),
+        AudioTagField::AlbumArtist => tag.get_string(ItemKey::AlbumArtist)?.to_string(),
+        AudioTagField::Album => tag.album()?.to_string(),
+        AudioTagField::Title => tag.title()?.to_string(),
+        AudioTagField::Track => format!("{:0padding$}", tag.track()?),
+        AudioTagField::Disc => format!("{:0padding$}", tag.disk()?),
+        AudioTagField::Year => tag.date()?.year.to_string(),
+        AudioTagField::Genre => tag.genre()?.to_string(),
+    };
+
+    // NOTE: tags can hold characters that aren't allowed in file names, like the slash in AC/DC
+    let value = replace_illegal_chars(value.trim());
+
+    (!value.is_empty()).then_some(value)
+}
+fn task_audio_tag(
+    file: &mut WorkingFile,
+    field: &AudioTagField,
+    at_start: &bool,
+    separator: &String,
+    padding: &u64,
+    fallback: &str,
+    active: &bool,
+) {
+    if *active {
+        let tag_value = match file.is_dir {
+            true => None,
+            false => audio_tag_value(&file.source, field, padding),
+        };
+        // without the tag and without a fallback the name is left alone
+        let Some(tag_value) = tag_value.or_else(|| (!fallback.is_empty()).then(|| fallback.to_string())) else {
+            return;
+        };
+
+        let file_name = file.target.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+        // NOTE: after Clear All only ".mp3" is left, that is an empty stem and not a dotfile here
+        let (file_stem, file_extension) = match file_name.rsplit_once('.') {
+            _ if file_name == BLANK_NAME => ("", None),
+            Some((stem, extension)) => (stem, Some(extension)),
+            None => (file_name.as_str(), None),
+        };
+
+        let new_stem = if file_stem.is_empty() {
+            tag_value
+        } else if *at_start {
+            format!("{tag_value}{separator}{file_stem}")
+        } else {
+            format!("{file_stem}{separator}{tag_value}")
+        };
+
+        match file_extension {
+            Some(file_extension) => file.target.set_file_name(format!("{new_stem}.{file_extension}")),
+            None => file.target.set_file_name(new_stem),
+        }
+    }
+}
+/// A file the template can't name keeps the name it had, the error is reported on the task.
+fn task_template(file: &mut WorkingFile, position: u64, template: &NameTemplate, start_num: &u64, active: &bool) -> Result<(), String> {
+    if *active {
+        let file_name = file.target.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let current_name = if file_name == BLANK_NAME { "" } else { file_name.as_str() };
+
+        let counter = start_num.checked_add(position).ok_or(format!("the counter goes past {}, lower the start number", u64::MAX))?;
+        let mut new_file_name = template.render(file, counter, current_name)?;
+        if new_file_name.is_empty() {
+            new_file_name = BLANK_NAME.to_string();
+        }
+
+        file.target.set_file_name(new_file_name);
+    }
+    Ok(())
+}
+fn task_filter_name(file: &mut WorkingFile, inclusive: &bool, matcher: Option<&NameMatcher>, match_path: &bool, folders: &[PathBuf]) {
+    // NOTE: there is no matcher while the name is still empty
+    if let Some(matcher) = matcher {
         if let Some(file_name) = file.target.file_name() {
             let file_name_string = file_name.to_string_lossy();
-            let found = file_name_string.contains(name);
+            let found = match match_path {
+                true => matcher.matches(&relative_path(&file.source, &file_name_string, folders)),
+                false => matcher.matches(&file_name_string),
+            };
 
             if (found && *inclusive) || (!found && !inclusive) {
                 file.active = false;
@@ -320,20 +542,18 @@ fn task_filter_doc_type(file: &mut WorkingFile, inclusive: &bool, doc_types: &[S
 fn task_filter_time(file: &mut WorkingFile, before: &bool, time: &Option<String>) {
     if let Some(time) = time {
         if let Ok(filter_datetime) = OffsetDateTime::parse(time, &Iso8601::DEFAULT) {
-            if let Ok(metadata) = file.source.metadata() {
-                if let Ok(modified) = metadata.modified() {
-                    let file_datetime = OffsetDateTime::from(modified);
-
-                    if *before {
-                        // "Filter Older Dates" — hide files older than selected date
-                        if file_datetime < filter_datetime {
-                            file.active = false;
-                        }
-                    } else {
-                        // "Filter Newer Dates" — hide files newer than selected date
-                        if file_datetime > filter_datetime {
-                            file.active = false;
-                        }
+            if let Some(modified) = file.metadata.and_then(|metadata| metadata.modified) {
+                let file_datetime = OffsetDateTime::from(modified);
+
+                if *before {
+                    // "Filter Older Dates" — hide files older than selected date
+                    if file_datetime < filter_datetime {
+                        file.active = false;
+                    }
+                } else {
+                    // "Filter Newer Dates" — hide files newer than selected date
+                    if file_datetime > filter_datetime {
+                        file.active = false;
                     }
                 }
             }
@@ -345,14 +565,12 @@ fn task_filter_time_period(file: &mut WorkingFile, inclusive: &bool, start_time:
         if let (Ok(start_dt), Ok(end_dt)) =
             (OffsetDateTime::parse(start_time, &Iso8601::DEFAULT), OffsetDateTime::parse(end_time, &Iso8601::DEFAULT))
         {
-            if let Ok(metadata) = file.source.metadata() {
-                if let Ok(modified) = metadata.modified() {
-                    let file_datetime = OffsetDateTime::from(modified);
-                    let in_range = file_datetime >= start_dt && file_datetime <= end_dt;
+            if let Some(modified) = file.metadata.and_then(|metadata| metadata.modified) {
+                let file_datetime = OffsetDateTime::from(modified);
+                let in_range = file_datetime >= start_dt && file_datetime <= end_dt;
 
-                    if (in_range && *inclusive) || (!in_range && !inclusive) {
-                        file.active = false;
-                    }
+                if (in_range && *inclusive) || (!in_range && !inclusive) {
+                    file.active = false;
                 }
             }
         }
@@ -360,8 +578,8 @@ fn task_filter_time_period(file: &mut WorkingFile, inclusive: &bool, start_time:
 }
 
 fn task_filter_size(file: &mut WorkingFile, greater_than: &bool, byte_base_size: &u64, size: &u64) {
-    if let Ok(metadata) = file.source.metadata() {
-        let file_size = metadata.len();
+    if let Some(metadata) = file.metadata {
+        let file_size = metadata.len;
         // byte_base_size is a tier: 0=Bytes, 1=KB, 2=MB, 3=GB, 4=TB
         let multiplier: u64 = 1024_u64.pow(*byte_base_size as u32);
         let search_size = multiplier * size;
@@ -378,58 +596,340 @@ fn task_filter_size(file: &mut WorkingFile, greater_than: &bool, byte_base_size:
     }
 }
 
-#[tauri::command]
-pub fn process_tasks_on_working_files(state: &State<'_, Mutex<AppState>>) {
-    let mut state = state.lock().unwrap();
-    let state = &mut *state;
+fn task_filter_kind(file: &mut WorkingFile, kind: &EntryKind) {
+    match kind {
+        EntryKind::Both => {}
+        EntryKind::Files => file.active &= !file.is_dir,
+        EntryKind::Directories => file.active &= file.is_dir,
+    }
+}
 
-    for (index, file) in &mut state.working_files.iter_mut().enumerate() {
-        file.target = file.source.clone();
-        file.active = true;
+fn task_filter_duplicates(file: &mut WorkingFile, keep: &DuplicateKeep) {
+    match keep {
+        DuplicateKeep::First => file.active &= file.copy_index == 0,
+        DuplicateKeep::Unique => file.active &= file.duplicate_group.is_none(),
+        DuplicateKeep::Copies => file.active &= file.copy_index > 0,
+    }
+}
 
-        for task in &state.tasks {
-            match task {
-                Task::CustomText { text, at_start, active } => {
-                    task_custom_text(file, text, at_start, active);
-                }
-                Task::FindAndReplace { find_text, replace_text, active } => {
-                    task_find_and_replace(file, find_text, replace_text, active);
-                }
+/// Filters that only look at what the file is, not at the name the other tasks build.
+fn in_filter_stage(task: &Task) -> bool {
+    match task {
+        Task::FilterName { name_target, .. } | Task::FilterDocType { name_target, .. } => *name_target == NameTarget::Original,
+        Task::FilterTime { .. } | Task::FilterTimePeriod { .. } | Task::FilterSize { .. } | Task::FilterKind { .. } => true,
+        Task::FilterDuplicates { .. } => true,
+        Task::FilterGroup { filters, .. } => filters.iter().all(in_filter_stage),
+        _ => false,
+    }
+}
 
-                Task::ClearAll { active } => {
-                    task_clear_all(file, active);
-                }
+/// What filters need besides the file itself, built once per run.
+struct FilterContext<'a> {
+    name_matchers: HashMap<(&'a str, MatchMode, bool), NameMatcher>,
+    folders: &'a [PathBuf],
+}
 
-                Task::ChangeCase { case_choice, active } => {
-                    task_change_case(file, case_choice, active);
-                }
-                Task::NumSequence { start_num, num_padding, separator, at_start, active } => {
-                    task_num_sequence(file, index, start_num, num_padding, at_start, separator, active);
-                }
+/// Every FilterName of the task, also the ones inside groups and conditions.
+fn name_filters<'a>(task: &'a Task, found: &mut Vec<&'a Task>) {
+    match task {
+        Task::FilterName { .. } => found.push(task),
+        Task::FilterGroup { filters, .. } => filters.iter().for_each(|filter| name_filters(filter, found)),
+        _ => {
+            if let Some(condition) = task_condition(task) {
+                name_filters(condition, found);
+            }
+        }
+    }
+}
+
+fn apply_filter(file: &mut WorkingFile, filter: &Task, context: &FilterContext) {
+    match filter {
+        Task::FilterName { inclusive, name, match_mode, case_insensitive, match_path, .. } => {
+            let matcher = context.name_matchers.get(&(name.as_str(), *match_mode, *case_insensitive));
+            task_filter_name(file, inclusive, matcher, match_path, context.folders)
+        }
+        Task::FilterDocType { inclusive, doc_types, .. } => task_filter_doc_type(file, inclusive, doc_types),
+        Task::FilterTime { before, time } => task_filter_time(file, before, time),
+        Task::FilterTimePeriod { inclusive, start_time, end_time } => task_filter_time_period(file, inclusive, start_time, end_time),
+        Task::FilterSize { greater_than, byte_base_size, size } => task_filter_size(file, greater_than, byte_base_size, size),
+        Task::FilterKind { kind } => task_filter_kind(file, kind),
+        Task::FilterGroup { operator, filters } => task_filter_group(file, operator, filters, context),
+        Task::FilterDuplicates { keep } => task_filter_duplicates(file, keep),
+        _ => {}
+    }
+}
 
-                Task::Date { year, month, day, at_start, separator, active } => {
-                    task_date(file, year, month, day, at_start, separator, active);
+pub(crate) fn task_condition(task: &Task) -> Option<&Task> {
+    match task {
+        Task::CustomText { condition, .. }
+        | Task::FindAndReplace { condition, .. }
+        | Task::ClearAll { condition, .. }
+        | Task::ChangeCase { condition, .. }
+        | Task::NumSequence { condition, .. }
+        | Task::Date { condition, .. }
+        | Task::Time { condition, .. }
+        | Task::AudioTag { condition, .. }
+        | Task::Template { condition, .. } => condition.as_deref(),
+        _ => None,
+    }
+}
+
+/// Whether the filter would keep the file, without filtering it. Conditions and filter groups are built on this,
+/// a transform with a condition only changes the files its filter would keep.
+fn filter_keeps(file: &WorkingFile, filter: &Task, context: &FilterContext) -> bool {
+    let target = if in_filter_stage(filter) { file.source.clone() } else { file.target.clone() };
+    let mut probe = WorkingFile { target, active: true, ..file.clone() };
+    apply_filter(&mut probe, filter, context);
+    probe.active
+}
+
+fn task_filter_group(file: &mut WorkingFile, operator: &GroupOperator, filters: &[Task], context: &FilterContext) {
+    // NOTE: an empty group is still being set up, it doesn't filter anything yet
+    if filters.is_empty() {
+        return;
+    }
+
+    let keeps = match operator {
+        GroupOperator::All => filters.iter().all(|filter| filter_keeps(file, filter, context)),
+        GroupOperator::Any => filters.iter().any(|filter| filter_keeps(file, filter, context)),
+        GroupOperator::None => !filters.iter().any(|filter| filter_keeps(file, filter, context)),
+    };
+    file.active &= keeps;
+}
+
+/// Where each file is in a NumSequence or the counter of a Template, numbered in list order. Empty for other tasks.
+fn sequence_positions(files: &[WorkingFile], task: &Task, context: &FilterContext) -> Vec<u64> {
+    let (active_only, per_folder) = match task {
+        Task::NumSequence { active_only, per_folder, .. } => (*active_only, *per_folder),
+        // the template counts only the files that are renamed, over all folders
+        Task::Template { .. } => (true, false),
+        _ => return Vec::new(),
+    };
+
+    // how many files the task has numbered so far, per folder when it restarts per folder
+    let mut counters: HashMap<PathBuf, u64> = HashMap::new();
+    files
+        .iter()
+        .map(|file| {
+            if task_condition(task).is_some_and(|condition| !filter_keeps(file, condition, context)) {
+                return 0;
+            }
+            // NOTE: with active_only, files a filter further up the list removed don't take a number
+            let folder = match per_folder {
+                true => file.source.parent().map(Path::to_path_buf).unwrap_or_default(),
+                false => PathBuf::new(),
+            };
+            let counter = counters.entry(folder).or_insert(0);
+            let position = *counter;
+            if file.active || !active_only {
+                *counter += 1;
+            }
+            position
+        })
+        .collect()
+}
+
+fn apply_task(
+    file: &mut WorkingFile,
+    task: &Task,
+    position: Option<u64>,
+    find_regex: Option<&Regex>,
+    template: Option<&NameTemplate>,
+    context: &FilterContext,
+) -> Result<(), String> {
+    match task {
+        Task::CustomText { text, at_start, active, .. } => {
+            task_custom_text(file, text, at_start, active);
+        }
+        Task::FindAndReplace { find_text, replace_text, use_regex, first_match_only, active, .. } => {
+            task_find_and_replace(file, find_text, replace_text, find_regex, use_regex, first_match_only, active);
+        }
+
+        Task::ClearAll { active, .. } => {
+            task_clear_all(file, active);
+        }
+
+        Task::ChangeCase { case_choice, active, .. } => {
+            task_change_case(file, case_choice, active);
+        }
+        Task::NumSequence { start_num, num_padding, separator, at_start, step, numerals, active, .. } => {
+            task_num_sequence(file, position.unwrap_or(0), start_num, num_padding, at_start, separator, step, numerals, active)?;
+        }
+
+        Task::Date { year, month, day, at_start, separator, date_sources, active, .. } => {
+            task_date(file, year, month, day, at_start, separator, date_sources, active);
+        }
+
+        Task::Time { at_start, separator, date_sources, active, .. } => {
+            task_time(file, at_start, separator, date_sources, active);
+        }
+        Task::AudioTag { field, at_start, separator, padding, fallback, active, .. } => {
+            task_audio_tag(file, field, at_start, separator, padding, fallback, active);
+        }
+        Task::Template { start_num, active, .. } => {
+            if let Some(template) = template {
+                task_template(file, position.unwrap_or(0), template, start_num, active)?;
+            }
+        }
+        Task::FilterName { .. }
+        | Task::FilterDocType { .. }
+        | Task::FilterTime { .. }
+        | Task::FilterTimePeriod { .. }
+        | Task::FilterSize { .. }
+        | Task::FilterKind { .. }
+        | Task::FilterGroup { .. }
+        | Task::FilterDuplicates { .. } => {
+            apply_filter(file, task, context);
+        }
+    }
+    Ok(())
+}
+
+/// Runs `apply` on every file with its index, the list is split between one thread per core.
+/// Small lists stay on this thread, starting threads would cost more than it saves.
+fn for_each_file(files: &mut [WorkingFile], apply: &(dyn Fn(usize, &mut WorkingFile) + Sync)) {
+    const MIN_FILES_PER_THREAD: usize = 512;
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(files.len() / MIN_FILES_PER_THREAD);
+    if threads < 2 {
+        files.iter_mut().enumerate().for_each(|(index, file)| apply(index, file));
+        return;
+    }
+
+    let chunk_size = files.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        for (chunk_index, chunk) in files.chunks_mut(chunk_size).enumerate() {
+            scope.spawn(move || {
+                for (offset, file) in chunk.iter_mut().enumerate() {
+                    apply(chunk_index * chunk_size + offset, file);
                 }
+            });
+        }
+    });
+}
 
-                Task::Time { at_start, separator, active } => {
-                    task_time(file, at_start, separator, active);
+pub fn process_tasks_on_working_files(state: &mut AppState) {
+    // compile every pattern once per run, invalid ones are reported and the task is skipped
+    let mut task_errors: Vec<TaskError> = Vec::new();
+    let find_regexes: Vec<Option<Regex>> = state
+        .tasks
+        .iter()
+        .enumerate()
+        .map(|(task_index, task)| match task {
+            Task::FindAndReplace { find_text, use_regex, case_insensitive, first_match_only, .. } => {
+                match compile_find_and_replace(find_text, use_regex, case_insensitive, first_match_only) {
+                    Ok(find_regex) => find_regex,
+                    Err(e) => {
+                        task_errors.push(TaskError { task_index, message: e.to_string() });
+                        None
+                    }
                 }
-                Task::FilterName { inclusive, name } => {
-                    task_filter_name(file, inclusive, name);
+            }
+            _ => None,
+        })
+        .collect();
+    let templates: Vec<Option<NameTemplate>> = state
+        .tasks
+        .iter()
+        .enumerate()
+        .map(|(task_index, task)| match task {
+            Task::Template { template, pattern, .. } => match NameTemplate::parse(template, pattern) {
+                Ok(template) => Some(template),
+                Err(message) => {
+                    task_errors.push(TaskError { task_index, message });
+                    None
                 }
-                Task::FilterDocType { inclusive, doc_types } => {
-                    task_filter_doc_type(file, inclusive, doc_types);
+            },
+            _ => None,
+        })
+        .collect();
+    let mut filter_context = FilterContext { name_matchers: HashMap::new(), folders: &state.folders };
+    for (task_index, task) in state.tasks.iter().enumerate() {
+        let mut found: Vec<&Task> = Vec::new();
+        name_filters(task, &mut found);
+
+        for filter in found {
+            if let Task::FilterName { name, match_mode, case_insensitive, .. } = filter {
+                if name.is_empty() {
+                    continue;
                 }
-                Task::FilterTime { before, time } => {
-                    task_filter_time(file, before, time);
+                let key = (name.as_str(), *match_mode, *case_insensitive);
+                match NameMatcher::new(name, *match_mode, *case_insensitive) {
+                    Ok(matcher) => {
+                        filter_context.name_matchers.insert(key, matcher);
+                    }
+                    Err(message) => task_errors.push(TaskError { task_index, message }),
                 }
-                Task::FilterTimePeriod { inclusive, start_time, end_time } => {
-                    task_filter_time_period(file, inclusive, start_time, end_time);
+            }
+        }
+    }
+    let invalid_tasks: Vec<usize> = task_errors.iter().map(|e| e.task_index).collect();
+    state.task_errors = task_errors;
+
+    // the filter stage decides which files are active before anything renames or numbers them,
+    // the other tasks follow in list order
+    let task_order: Vec<usize> = (0..state.tasks.len())
+        .filter(|&task_index| in_filter_stage(&state.tasks[task_index]))
+        .chain((0..state.tasks.len()).filter(|&task_index| !in_filter_stage(&state.tasks[task_index])))
+        .collect();
+    let ordered_tasks: Vec<Task> = task_order.iter().map(|&task_index| state.tasks[task_index].clone()).collect();
+
+    // NOTE: the tasks before the first one that changed would leave the files as they did last run, they are skipped
+    let mut cache = std::mem::take(&mut state.preview_cache);
+    if !cache.sources.iter().eq(state.working_files.iter().map(|file| &file.source)) {
+        cache = PreviewCache { sources: state.working_files.iter().map(|file| file.source.clone()).collect(), ..PreviewCache::default() };
+    }
+    let reused = ordered_tasks.iter().zip(&cache.tasks).take_while(|(task, cached)| task == cached).count();
+    cache.checkpoints.truncate(reused);
+    cache.failures.truncate(reused);
+    state.task_errors.extend(cache.failures.iter().flatten().cloned());
+
+    for (index, file) in state.working_files.iter_mut().enumerate() {
+        match cache.checkpoints.last() {
+            Some(checkpoint) => {
+                file.target = file.source.with_file_name(&checkpoint[index].name);
+                file.active = checkpoint[index].active;
+            }
+            None => {
+                file.target = file.source.clone();
+                file.active = true;
+            }
+        }
+    }
+
+    for &task_index in &task_order[reused..] {
+        let task = &state.tasks[task_index];
+        // the first file in list order a task fails on, whichever thread got there first
+        let failure: Mutex<Option<(usize, String)>> = Mutex::new(None);
+        if !invalid_tasks.contains(&task_index) {
+            let positions = sequence_positions(&state.working_files, task, &filter_context);
+            let find_regex = find_regexes[task_index].as_ref();
+            let template = templates[task_index].as_ref();
+
+            for_each_file(&mut state.working_files, &|index, file| {
+                if task_condition(task).is_some_and(|condition| !filter_keeps(file, condition, &filter_context)) {
+                    return;
                 }
-                Task::FilterSize { greater_than, byte_base_size, size } => {
-                    task_filter_size(file, greater_than, byte_base_size, size);
+                if let Err(message) = apply_task(file, task, positions.get(index).copied(), find_regex, template, &filter_context) {
+                    let mut failure = failure.lock().unwrap();
+                    if failure.as_ref().is_none_or(|(first, _)| index < *first) {
+                        *failure = Some((index, message));
+                    }
                 }
-            }
+            });
         }
+        let failure = failure.into_inner().unwrap().map(|(_, message)| TaskError { task_index, message });
+        state.task_errors.extend(failure.clone());
+        cache.failures.push(failure);
+
+        let checkpoint = state
+            .working_files
+            .iter()
+            .map(|file| Checkpoint { name: target_name(file).as_os_str().to_os_string(), active: file.active })
+            .collect();
+        cache.checkpoints.push(checkpoint);
     }
+
+    cache.tasks = ordered_tasks;
+    state.preview_cache = cache;
 }
@@ -0,0 +1,60 @@
+use crate::{Output, Path, Task};
+
+use std::fs;
+
+pub(crate) const TEMPLATE_VERSION: u32 = 1;
+const TEMPLATE_NAME_MAX_LEN: usize = 100;
+const ILLEGAL_NAME_CHARS: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Template {
+    pub(crate) version: u32,
+    pub(crate) name: String,
+    pub(crate) tasks: Vec<Task>,
+    pub(crate) output: Output,
+}
+
+/// Template names double as file names, so they have to be safe on every platform.
+pub fn validate_template_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+
+    if name.is_empty() {
+        return Err("Template name can't be empty".to_string());
+    }
+    if name.chars().count() > TEMPLATE_NAME_MAX_LEN {
+        return Err(format!("Template name can't be longer than {TEMPLATE_NAME_MAX_LEN} characters"));
+    }
+    if name.starts_with('.') || name.chars().any(|c| ILLEGAL_NAME_CHARS.contains(&c) || c.is_control()) {
+        return Err(format!("Template name \"{name}\" contains characters that aren't allowed"));
+    }
+
+    Ok(name.to_string())
+}
+
+/// Check the version before the full parse, so a newer file gives a useful error instead of a serde one.
+fn parse_template(contents: &str) -> Result<Template, String> {
+    let value: serde_json::Value = serde_json::from_str(contents).map_err(|e| format!("Template is not valid JSON: {e}"))?;
+
+    let version = value.get("version").and_then(|v| v.as_u64()).ok_or("Template is missing a version".to_string())?;
+    if version == 0 || version > u64::from(TEMPLATE_VERSION) {
+        return Err(format!("Template version {version} is not supported by this version of Filera"));
+    }
+
+    let template: Template = serde_json::from_value(value).map_err(|e| format!("Template is invalid: {e}"))?;
+    validate_template_name(&template.name)?;
+
+    Ok(template)
+}
+
+pub fn read_template(path: &Path) -> Result<Template, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse_template(&contents)
+}
+
+pub fn write_template(path: &Path, template: &Template) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let contents = serde_json::to_string_pretty(template).map_err(|e| e.to_string())?;
+    fs::write(path, contents).map_err(|e| e.to_string())
+}
@@ -0,0 +1,111 @@
+use crate::{MoveVerification, Path, PathBuf};
+
+use std::fs::{self, File, FileTimes, Metadata};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A free name next to the path, on the same filesystem so parking a file there is a plain rename.
+pub(crate) fn temporary_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let process_id = std::process::id();
+
+    (0..)
+        .map(|n| path.with_file_name(format!(".{file_name}.filera-{process_id}-{n}.tmp")))
+        .find(|candidate| candidate.symlink_metadata().is_err())
+        .unwrap()
+}
+
+/// Renames the file, or copies it over when the destination is on another filesystem.
+/// `progress` gets the bytes copied so far and the file size, it is only called when copying.
+pub fn move_file(source: &Path, destination: &Path, verification: MoveVerification, progress: &mut dyn FnMut(u64, u64)) -> Result<()> {
+    match fs::rename(source, destination) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices && source.is_dir() => {
+            Err(Error::new(ErrorKind::Unsupported, "folders can't be moved to another filesystem, move their files instead"))
+        }
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => move_across_devices(source, destination, verification, progress),
+        result => result,
+    }
+}
+
+/// The copy goes to a temporary file next to the destination and is only put in place once it checks out,
+/// so an interrupted move never leaves a half written file under the new name.
+fn move_across_devices(
+    source: &Path,
+    destination: &Path,
+    verification: MoveVerification,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<()> {
+    let metadata = fs::metadata(source)?;
+    let partial = temporary_path(destination);
+
+    let copied = copy_verified(source, &partial, &metadata, verification, progress).and_then(|()| fs::rename(&partial, destination));
+    if let Err(e) = copied {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+
+    // keep exactly one copy of the file, the batch reports it as failed
+    if let Err(e) = fs::remove_file(source) {
+        let _ = fs::remove_file(destination);
+        return Err(Error::new(e.kind(), format!("the original could not be removed after copying: {e}")));
+    }
+    Ok(())
+}
+
+fn copy_verified(
+    source: &Path,
+    target: &Path,
+    metadata: &Metadata,
+    verification: MoveVerification,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<()> {
+    let total = metadata.len();
+    let mut reader = File::open(source)?;
+    let mut writer = File::create_new(target)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut copied: u64 = 0;
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..read])?;
+        if verification == MoveVerification::Checksum {
+            hasher.update(&buffer[..read]);
+        }
+        copied += read as u64;
+        progress(copied, total);
+    }
+    writer.sync_all()?;
+
+    let mut times = FileTimes::new();
+    if let Ok(modified) = metadata.modified() {
+        times = times.set_modified(modified);
+    }
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    writer.set_times(times)?;
+    writer.set_permissions(metadata.permissions())?;
+    drop(writer);
+
+    let written = fs::metadata(target)?.len();
+    if written != total {
+        return Err(Error::new(ErrorKind::InvalidData, format!("the copy is {written} bytes but the original is {total}")));
+    }
+    if verification == MoveVerification::Checksum && hash_file(target)? != hasher.finalize() {
+        return Err(Error::new(ErrorKind::InvalidData, "the checksum of the copy does not match the original"));
+    }
+    Ok(())
+}
+
+fn hash_file(path: &Path) -> Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize())
+}
@@ -1,6 +1,7 @@
-use crate::{AppState, FileStatusResponse, HashSet, Mutex, SelectedFileStatusAnchor, State};
+use crate::{AppState, FileStatusResponse, HashSet, Mutex, SelectedFileStatusAnchor, SelectionDelta, State, StatusWindow};
 
-use crate::atomics::{apply_search, build_response, convert_working_files_to_file_status};
+use crate::atomics::{apply_search, build_response, build_stats, convert_working_files_to_file_status};
+use crate::pipeline;
 
 fn set_file_status_selected(state: &mut AppState, stable_id: usize, selected: bool) {
     if let Some(filestatus) = state.file_statuses.get_mut(stable_id) {
@@ -32,6 +33,22 @@ fn set_single_selection(state: &mut AppState, visible_index: usize, stable_id: u
     set_file_status_selected(state, stable_id, true);
 }
 
+/// What changed between the selection before a click and now.
+fn selection_delta(state: &AppState, before: &HashSet<usize>) -> SelectionDelta {
+    let empty = HashSet::new();
+    let after = state.selected_filestatuses.as_ref().unwrap_or(&empty);
+
+    let mut selected: Vec<usize> = after.difference(before).copied().collect();
+    let mut deselected: Vec<usize> = before.difference(after).copied().collect();
+    selected.sort_unstable();
+    deselected.sort_unstable();
+    SelectionDelta { selected, deselected, stats: build_stats(state) }
+}
+
+fn selection_before(state: &AppState) -> HashSet<usize> {
+    state.selected_filestatuses.clone().unwrap_or_default()
+}
+
 fn visible_range_stable_ids(state: &AppState, start: usize, end: usize) -> Option<Vec<usize>> {
     if let Some(indices) = &state.filtered_filestatus_indices {
         indices.get(start..=end).map(|range| range.to_vec())
@@ -47,13 +64,11 @@ pub fn user_filestatus_click(
     visible_index: usize,
     stable_id: usize,
     state: State<'_, Mutex<AppState>>,
-) -> FileStatusResponse {
-    {
-        let mut state = state.lock().unwrap();
-        set_single_selection(&mut state, visible_index, stable_id);
-    }
-    apply_search(&state);
-    build_response(&state)
+) -> SelectionDelta {
+    let mut state = state.lock().unwrap();
+    let before = selection_before(&state);
+    set_single_selection(&mut state, visible_index, stable_id);
+    selection_delta(&state, &before)
 }
 
 #[tauri::command]
@@ -61,53 +76,51 @@ pub fn user_filestatus_ctrl_click(
     visible_index: usize,
     stable_id: usize,
     state: State<'_, Mutex<AppState>>,
-) -> FileStatusResponse {
-    {
-        let mut state = state.lock().unwrap();
-        let already_selected = state
-            .selected_filestatuses
-            .as_ref()
-            .map_or(false, |selected| selected.contains(&stable_id));
-
-        if already_selected {
-            set_file_status_selected(&mut state, stable_id, false);
-
-            let selection_is_empty = if let Some(selected) = &mut state.selected_filestatuses {
-                selected.remove(&stable_id);
-                selected.is_empty()
-            } else {
-                false
-            };
-
-            if selection_is_empty {
-                state.selected_filestatuses = None;
-            }
-
-            if state
-                .selected_filestatus_anchor
-                .as_ref()
-                .map_or(false, |anchor| anchor.stable_id == stable_id)
-            {
-                state.selected_filestatus_anchor = None;
-            }
+) -> SelectionDelta {
+    let mut state = state.lock().unwrap();
+    let before = selection_before(&state);
+    let already_selected = state
+        .selected_filestatuses
+        .as_ref()
+        .is_some_and(|selected| selected.contains(&stable_id));
+
+    if already_selected {
+        set_file_status_selected(&mut state, stable_id, false);
+
+        let selection_is_empty = if let Some(selected) = &mut state.selected_filestatuses {
+            selected.remove(&stable_id);
+            selected.is_empty()
         } else {
-            if state.selected_filestatuses.is_none() {
-                state.selected_filestatuses = Some(HashSet::new());
-            }
+            false
+        };
 
-            if let Some(selected) = &mut state.selected_filestatuses {
-                selected.insert(stable_id);
-            }
+        if selection_is_empty {
+            state.selected_filestatuses = None;
+        }
 
-            state.selected_filestatus_anchor = Some(SelectedFileStatusAnchor {
-                stable_id,
-                visible_index,
-            });
-            set_file_status_selected(&mut state, stable_id, true);
+        if state
+            .selected_filestatus_anchor
+            .as_ref()
+            .is_some_and(|anchor| anchor.stable_id == stable_id)
+        {
+            state.selected_filestatus_anchor = None;
         }
+    } else {
+        if state.selected_filestatuses.is_none() {
+            state.selected_filestatuses = Some(HashSet::new());
+        }
+
+        if let Some(selected) = &mut state.selected_filestatuses {
+            selected.insert(stable_id);
+        }
+
+        state.selected_filestatus_anchor = Some(SelectedFileStatusAnchor {
+            stable_id,
+            visible_index,
+        });
+        set_file_status_selected(&mut state, stable_id, true);
     }
-    apply_search(&state);
-    build_response(&state)
+    selection_delta(&state, &before)
 }
 
 #[tauri::command]
@@ -115,64 +128,65 @@ pub fn user_filestatus_shift_click(
     visible_index: usize,
     stable_id: usize,
     state: State<'_, Mutex<AppState>>,
-) -> FileStatusResponse {
-    {
-        let mut state = state.lock().unwrap();
-
-        if state.selected_filestatuses.is_none() || state.selected_filestatus_anchor.is_none() {
-            set_single_selection(&mut state, visible_index, stable_id);
-        } else if let Some(anchor) = state.selected_filestatus_anchor.clone() {
-            let start = anchor.visible_index.min(visible_index);
-            let end = anchor.visible_index.max(visible_index);
-
-            if let Some(stable_ids) = visible_range_stable_ids(&state, start, end) {
-                if state.selected_filestatuses.is_none() {
-                    state.selected_filestatuses = Some(HashSet::new());
-                }
+) -> SelectionDelta {
+    let mut state = state.lock().unwrap();
+    let before = selection_before(&state);
 
-                for stable_id in stable_ids {
-                    if let Some(selected) = &mut state.selected_filestatuses {
-                        selected.insert(stable_id);
-                    }
+    if state.selected_filestatuses.is_none() || state.selected_filestatus_anchor.is_none() {
+        set_single_selection(&mut state, visible_index, stable_id);
+    } else if let Some(anchor) = state.selected_filestatus_anchor.clone() {
+        let start = anchor.visible_index.min(visible_index);
+        let end = anchor.visible_index.max(visible_index);
+
+        if let Some(stable_ids) = visible_range_stable_ids(&state, start, end) {
+            if state.selected_filestatuses.is_none() {
+                state.selected_filestatuses = Some(HashSet::new());
+            }
 
-                    set_file_status_selected(&mut state, stable_id, true);
+            for stable_id in stable_ids {
+                if let Some(selected) = &mut state.selected_filestatuses {
+                    selected.insert(stable_id);
                 }
-            } else {
-                eprintln!("error: visible filestatus range is out of bounds: {start:?}..={end:?}");
+
+                set_file_status_selected(&mut state, stable_id, true);
             }
+        } else {
+            eprintln!("error: visible filestatus range is out of bounds: {start:?}..={end:?}");
         }
     }
-    apply_search(&state);
-    build_response(&state)
+    selection_delta(&state, &before)
 }
 
 #[tauri::command]
-pub fn user_filestatus_selection_clear(state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
-    {
-        let mut state = state.lock().unwrap();
-        clear_selected_file_statuses(&mut state);
-        state.selected_filestatus_anchor = None;
-    }
-    apply_search(&state);
-    build_response(&state)
+pub fn user_filestatus_selection_clear(state: State<'_, Mutex<AppState>>) -> SelectionDelta {
+    let mut state = state.lock().unwrap();
+    let before = selection_before(&state);
+    clear_selected_file_statuses(&mut state);
+    state.selected_filestatus_anchor = None;
+    selection_delta(&state, &before)
 }
 
 #[tauri::command]
 pub fn user_filestatus_selection_delete(state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
-    {
-        let mut state = state.lock().unwrap();
-        if let Some(selected) = state.selected_filestatuses.take() {
-            let mut indices: Vec<usize> = selected.into_iter().collect();
-            indices.sort_unstable();
-            indices.into_iter().rev().for_each(|i| {
-                let src = state.working_files[i].source.to_string_lossy().to_string();
-                state.working_files.remove(i);
-                state.file_names.retain(|path| path != &src);
-            });
-        }
-        state.selected_filestatus_anchor = None;
+    let mut state = state.lock().unwrap();
+    if let Some(selected) = state.selected_filestatuses.take() {
+        let mut indices: Vec<usize> = selected.into_iter().collect();
+        indices.sort_unstable();
+        indices.into_iter().rev().for_each(|i| {
+            let src = state.working_files[i].source.to_string_lossy().to_string();
+            state.working_files.remove(i);
+            state.file_names.retain(|path| path != &src);
+        });
     }
-    convert_working_files_to_file_status(&state);
-    apply_search(&state);
+    state.selected_filestatus_anchor = None;
+    convert_working_files_to_file_status(&mut state);
+    apply_search(&mut state);
     build_response(&state)
 }
+
+/// The table scrolled to other rows, see StatusWindow.
+#[tauri::command]
+pub fn user_filestatus_window(offset: usize, limit: usize, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
+    let mut state = state.lock().unwrap();
+    pipeline::update_status_window(&mut state, StatusWindow { offset, limit })
+}
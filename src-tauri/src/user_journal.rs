@@ -0,0 +1,43 @@
+use crate::journal::{load_journal, save_journal, undo_batch, UndoReport};
+use crate::user_std::progress_emitter;
+use crate::{BatchControl, ExecutionProgress, PathBuf, BATCH_RUNNING};
+
+use std::sync::atomic::Ordering;
+use tauri::Manager;
+
+const JOURNAL_FILE_NAME: &str = "journal.json";
+
+pub fn journal_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    Ok(dir.join(JOURNAL_FILE_NAME))
+}
+
+#[tauri::command]
+pub async fn user_undo_last_batch(app: tauri::AppHandle) -> Result<UndoReport, String> {
+    let control = app.state::<BatchControl>();
+    if control.running.swap(true, Ordering::SeqCst) {
+        return Err(BATCH_RUNNING.to_string());
+    }
+
+    // NOTE: moving files back can take as long as the batch did, so it runs on a worker thread too
+    let worker_app = app.clone();
+    let worker = tauri::async_runtime::spawn_blocking(move || undo_last_batch(&worker_app, &mut progress_emitter(&worker_app)));
+    let undone = worker.await.map_err(|e| e.to_string()).and_then(|undone| undone);
+    control.running.store(false, Ordering::SeqCst);
+    undone
+}
+
+fn undo_last_batch(app: &tauri::AppHandle, progress: &mut dyn FnMut(ExecutionProgress)) -> Result<UndoReport, String> {
+    let path = journal_path(app)?;
+    let mut journal = load_journal(&path);
+
+    let batch = journal.batches.pop().ok_or("There is nothing to undo".to_string())?;
+    let (report, remaining) = undo_batch(&batch, progress);
+    // NOTE: the batch is only gone once every entry is restored, skipped ones can be retried
+    if !remaining.entries.is_empty() {
+        journal.batches.push(remaining);
+    }
+
+    save_journal(&path, &journal)?;
+    Ok(report)
+}
@@ -1,126 +1,119 @@
-use crate::process_tasks::process_tasks_on_working_files;
-use crate::{AppState, FileStatusResponse, FileStatusStats, Mutex, PathBuf, State, Task};
-
-use crate::atomics::{
-    apply_search, apply_selections_to_filestatuses, build_response, convert_file_names_to_working_files,
-    convert_working_files_to_file_status, resolve_workingfile_duplicates, solve_duplicates, sort_file_names,
-    state_clear_selected_filestatuses, state_update_search, state_update_sort, state_update_tasks,
+use crate::journal::{record_batch, JournalBatch};
+use crate::pipeline;
+use crate::user_journal::journal_path;
+use crate::{
+    AppState, BatchControl, ConflictPolicy, ExecutionProgress, FileStatusResponse, FolderOptions, MoveVerification, Mutex, Output,
+    RenameResponse, SortOptions, State, Task, BATCH_RUNNING,
 };
 
+use crate::atomics::build_response;
+
 use notify_rust::Notification;
 use rfd::{AsyncMessageDialog, MessageDialogResult};
 // use std::time::SystemTime;
-use std::{fs::copy, fs::rename};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tauri::{Emitter, Manager};
 use tauri_plugin_notification::NotificationExt;
+
+// a batch of tens of thousands of renames would flood the window with events, so progress is sent at most this often
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(50);
 // use time::format_description::well_known::Iso8601;
 // use time::OffsetDateTime;
-use walkdir::WalkDir;
 
 #[tauri::command]
 pub fn user_open_files(file_names: Vec<String>, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
-    solve_duplicates(file_names, &state);
-    sort_file_names(&state);
-    convert_file_names_to_working_files(&state);
-    process_tasks_on_working_files(&state);
-    resolve_workingfile_duplicates(&state);
-    state_clear_selected_filestatuses(&state);
-    convert_working_files_to_file_status(&state);
-    apply_selections_to_filestatuses(&state);
-    apply_search(&state);
-    build_response(&state)
+    let mut state = state.lock().unwrap();
+    pipeline::open_files(&mut state, file_names)
 }
 
 #[tauri::command]
 pub fn user_open_folders(directories: Vec<String>, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
-    let mut file_names: Vec<String> = Vec::new();
-
-    for dir in directories {
-        for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
-            file_names.push(entry.path().to_string_lossy().to_string());
-        }
-    }
-
-    solve_duplicates(file_names, &state);
-    sort_file_names(&state);
-    convert_file_names_to_working_files(&state);
-    process_tasks_on_working_files(&state);
-    resolve_workingfile_duplicates(&state);
-    state_clear_selected_filestatuses(&state);
-    convert_working_files_to_file_status(&state);
-    apply_selections_to_filestatuses(&state);
-    apply_search(&state);
-    build_response(&state)
+    let mut state = state.lock().unwrap();
+    pipeline::open_paths(&mut state, directories)
 }
 
 #[tauri::command]
 pub fn user_dragdrop_files(files: Vec<String>, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
-    let mut file_names: Vec<String> = Vec::new();
-
-    for file in files {
-        let current_file = PathBuf::from(file.clone());
-
-        if current_file.is_file() {
-            file_names.push(file);
-        } else if current_file.is_dir() {
-            for entry in WalkDir::new(current_file).into_iter().filter_map(|e| e.ok()) {
-                file_names.push(entry.path().to_string_lossy().to_string());
-            }
-        }
-    }
+    let mut state = state.lock().unwrap();
+    pipeline::open_paths(&mut state, files)
+}
 
-    solve_duplicates(file_names, &state);
-    sort_file_names(&state);
-    convert_file_names_to_working_files(&state);
-    process_tasks_on_working_files(&state);
-    resolve_workingfile_duplicates(&state);
-    state_clear_selected_filestatuses(&state);
-    convert_working_files_to_file_status(&state);
-    apply_selections_to_filestatuses(&state);
-    apply_search(&state);
-    build_response(&state)
+#[tauri::command]
+pub fn user_update_folder_options(folder_options: FolderOptions, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
+    let mut state = state.lock().unwrap();
+    pipeline::update_folder_options(&mut state, folder_options)
 }
 
 #[tauri::command]
 pub fn user_clear_files(state: State<'_, Mutex<AppState>>) {
     let mut state = state.lock().unwrap();
-    state.file_names.clear();
-    state.working_files.clear();
-    state.selected_filestatuses = None;
-    state.selected_filestatus_anchor = None;
+    pipeline::clear_files(&mut state);
+}
+
+/// Reads the loaded files from the disk again, the preview is built from what was read when they were loaded.
+#[tauri::command]
+pub fn user_refresh_metadata(state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
+    let mut state = state.lock().unwrap();
+    pipeline::refresh_metadata(&mut state)
 }
 
 #[tauri::command]
 pub fn user_update_sort(sort_choice: String, sort_ascending: bool, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
-    state_update_sort(sort_choice, sort_ascending, &state);
-    sort_file_names(&state);
-    convert_file_names_to_working_files(&state);
-    process_tasks_on_working_files(&state);
-    resolve_workingfile_duplicates(&state);
-    state_clear_selected_filestatuses(&state);
-    convert_working_files_to_file_status(&state);
-    apply_selections_to_filestatuses(&state);
-    apply_search(&state);
-    build_response(&state)
+    let mut state = state.lock().unwrap();
+    pipeline::update_sort(&mut state, sort_choice, sort_ascending)
+}
+
+#[tauri::command]
+pub fn user_update_sort_options(sort_options: SortOptions, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
+    let mut state = state.lock().unwrap();
+    pipeline::update_sort_options(&mut state, sort_options)
 }
 
 #[tauri::command]
 pub fn user_update_tasks(task_list: Vec<Task>, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
-    state_update_tasks(task_list, &state);
-    process_tasks_on_working_files(&state);
-    resolve_workingfile_duplicates(&state);
-    convert_working_files_to_file_status(&state);
-    apply_selections_to_filestatuses(&state);
-    apply_search(&state);
-    build_response(&state)
+    let mut state = state.lock().unwrap();
+    pipeline::update_tasks(&mut state, task_list)
 }
 
 #[tauri::command]
 pub fn user_update_search(search: String, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
-    state_update_search(search, &state);
-    convert_working_files_to_file_status(&state);
-    apply_selections_to_filestatuses(&state);
-    apply_search(&state);
-    build_response(&state)
+    let mut state = state.lock().unwrap();
+    pipeline::update_search(&mut state, search)
+}
+
+#[tauri::command]
+pub fn user_update_output(
+    output_dropdown_choice: &str,
+    output_directory: &str,
+    state: State<'_, Mutex<AppState>>,
+) -> FileStatusResponse {
+    let mut state = state.lock().unwrap();
+    pipeline::update_output(&mut state, Output::from_choice(output_dropdown_choice, output_directory))
+}
+
+#[tauri::command]
+pub fn user_update_conflict_policy(conflict_policy: ConflictPolicy, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
+    let mut state = state.lock().unwrap();
+    pipeline::update_conflict_policy(&mut state, conflict_policy)
+}
+
+#[tauri::command]
+pub fn user_update_include_directories(include_directories: bool, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
+    let mut state = state.lock().unwrap();
+    pipeline::update_include_directories(&mut state, include_directories)
+}
+
+#[tauri::command]
+pub fn user_update_find_duplicates(find_duplicates: bool, state: State<'_, Mutex<AppState>>) -> FileStatusResponse {
+    let mut state = state.lock().unwrap();
+    pipeline::update_find_duplicates(&mut state, find_duplicates)
+}
+
+#[tauri::command]
+pub fn user_update_move_verification(move_verification: MoveVerification, state: State<'_, Mutex<AppState>>) {
+    let mut state = state.lock().unwrap();
+    pipeline::update_move_verification(&mut state, move_verification);
 }
 
 #[tauri::command]
@@ -129,27 +122,14 @@ pub async fn user_rename_files(
     output_directory: &str,
     state: State<'_, Mutex<AppState>>,
     app: tauri::AppHandle,
-) -> Result<FileStatusResponse, FileStatusResponse> {
-    // const REPLACE: &str = "replace";
-    const COPY: &str = "copy";
-    const MOVE: &str = "move";
-
+) -> Result<RenameResponse, FileStatusResponse> {
     println!("output dropdown choice= {output_dropdown_choice}");
     println!("output directory= {output_directory}");
 
-    let existing_response;
-    {
+    let existing_response = {
         let state = state.lock().unwrap();
-        existing_response = FileStatusResponse {
-            statuses: state.file_statuses.clone(),
-            stats: FileStatusStats {
-                total: state.file_statuses.len(),
-                selected: state.selected_filestatuses.as_ref().map_or(0, |s| s.len()),
-                filtered: state.filtered_count,
-                ready: state.file_statuses.len() - state.filtered_count,
-            },
-        };
-    }
+        build_response(&state)
+    };
 
     // Extract the needed data from the locked state without holding the lock across await points
     let (tasks_empty, files_empty) = {
@@ -166,7 +146,7 @@ pub async fn user_rename_files(
             .show()
             .await
         {
-            MessageDialogResult::Ok => Ok(existing_response),
+            MessageDialogResult::Ok => Ok(RenameResponse { files: existing_response, report: None }),
             _ => Err(existing_response),
         }
     }
@@ -179,7 +159,7 @@ pub async fn user_rename_files(
             .show()
             .await
         {
-            MessageDialogResult::Ok => Ok(existing_response),
+            MessageDialogResult::Ok => Ok(RenameResponse { files: existing_response, report: None }),
             _ => Err(existing_response),
         }
     }
@@ -192,7 +172,7 @@ pub async fn user_rename_files(
             .show()
             .await
         {
-            MessageDialogResult::Ok => Ok(existing_response),
+            MessageDialogResult::Ok => Ok(RenameResponse { files: existing_response, report: None }),
             _ => Err(existing_response),
         }
     }
@@ -207,107 +187,89 @@ pub async fn user_rename_files(
             .await
         {
             MessageDialogResult::Ok => {
-                match output_dropdown_choice {
-                    COPY => {
-                        // here is our copy commands
-                        // no longer uses fs::rename, instead uses a copy, with a new parent
-                        // directory
-                        let mut state = state.lock().unwrap();
-                        for file in &mut state.working_files {
-                            if file.active {
-                                let temp_filename = file.target.file_name();
-
-                                if let Some(t) = temp_filename {
-                                    file.target = PathBuf::from(output_directory).join(t);
-
-                                    let rename_result = copy(&file.source, &file.target);
-
-                                    if let Err(t) = rename_result {
-                                        println!("{t}");
-                                    }
-                                }
-                            }
-                        }
-                        state.file_names.clear();
-                        state.working_files.clear();
-                        let blank = FileStatusResponse {
-                            statuses: vec![],
-                            stats: FileStatusStats::default(),
-                        };
-
-                        app.notification()
-                            .builder()
-                            .title("Success")
-                            .body("Files converted successfully")
-                            .show()
-                            .unwrap();
-
-                        Ok(blank)
-                    }
-                    MOVE => {
-                        // here is our move commands
-                        // same rename function, but each file needs new parent directory
-                        let mut state = state.lock().unwrap();
-                        for file in &mut state.working_files {
-                            if file.active {
-                                let temp_filename = file.target.file_name();
-
-                                if let Some(t) = temp_filename {
-                                    file.target = PathBuf::from(output_directory).join(t);
-
-                                    let rename_result = rename(&file.source, &file.target);
-
-                                    if let Err(t) = rename_result {
-                                        println!("{t}");
-                                    }
-                                }
-                            }
-                        }
-                        state.file_names.clear();
-                        state.working_files.clear();
-                        let blank = FileStatusResponse {
-                            statuses: vec![],
-                            stats: FileStatusStats::default(),
+                let output = Output::from_choice(output_dropdown_choice, output_directory);
+
+                let control = app.state::<BatchControl>();
+                if control.running.swap(true, Ordering::SeqCst) {
+                    AsyncMessageDialog::new()
+                        .set_title("Warning")
+                        .set_description(BATCH_RUNNING)
+                        .set_buttons(rfd::MessageButtons::Ok)
+                        .show()
+                        .await;
+                    return Err(existing_response);
+                }
+                control.cancel.store(false, Ordering::SeqCst);
+
+                let started = {
+                    let mut state = state.lock().unwrap();
+                    state.output = output.clone();
+                    pipeline::start_batch(&mut state)
+                };
+                let mut batch = match started {
+                    Ok(batch) => batch,
+                    Err(message) => {
+                        control.running.store(false, Ordering::SeqCst);
+                        let refreshed_response = {
+                            let state = state.lock().unwrap();
+                            build_response(&state)
                         };
-
-                        app.notification()
-                            .builder()
-                            .title("Success")
-                            .body("Files converted successfully")
+                        AsyncMessageDialog::new()
+                            .set_title("Warning")
+                            .set_description(message)
+                            .set_buttons(rfd::MessageButtons::Ok)
                             .show()
-                            .unwrap();
-
-                        Ok(blank)
+                            .await;
+                        return Err(refreshed_response);
                     }
-                    _ => {
-                        // here is our replace commands
-                        let mut state = state.lock().unwrap();
-                        for file in &mut state.working_files {
-                            if file.active {
-                                let rename_result = rename(&file.source, &file.target);
-
-                                if let Err(t) = rename_result {
-                                    println!("{t}");
-                                }
-                            }
-                        }
-                        state.file_names.clear();
-                        state.working_files.clear();
-                        let blank = FileStatusResponse {
-                            statuses: vec![],
-                            stats: FileStatusStats::default(),
+                };
+
+                // NOTE: the batch runs on a worker thread without the state lock, so the window keeps responding
+                let worker_app = app.clone();
+                let worker = tauri::async_runtime::spawn_blocking(move || {
+                    let control = worker_app.state::<BatchControl>();
+                    batch.run(&mut progress_emitter(&worker_app), &control.cancel)
+                });
+                let executed = worker.await.map_err(|e| e.to_string()).and_then(|executed| executed);
+                control.running.store(false, Ordering::SeqCst);
+
+                let (report, journal_entries) = match executed {
+                    Ok(executed) => executed,
+                    Err(message) => {
+                        let refreshed_response = {
+                            let mut state = state.lock().unwrap();
+                            pipeline::refresh_conflicts(&mut state)
                         };
-
-                        app.notification()
-                            .builder()
-                            .title("Success")
-                            .body("Files converted successfully")
+                        AsyncMessageDialog::new()
+                            .set_title("Warning")
+                            .set_description(message)
+                            .set_buttons(rfd::MessageButtons::Ok)
                             .show()
-                            .unwrap();
-
-                        Ok(blank)
+                            .await;
+                        return Err(refreshed_response);
                     }
-                }
+                };
+
+                let files = {
+                    let mut state = state.lock().unwrap();
+                    pipeline::finish_batch(&mut state, &report)
+                };
+                record_journal(&app, JournalBatch::new(output, journal_entries));
+
+                let (title, body) = match (report.cancelled, report.failed, report.skipped) {
+                    (true, _, _) => (
+                        "Batch cancelled",
+                        format!("{} files converted before the batch was cancelled. The rest are still loaded.", report.succeeded),
+                    ),
+                    (false, 0, 0) => ("Success", "Files converted successfully".to_string()),
+                    (false, failed, skipped) => (
+                        "Batch finished with problems",
+                        format!("{} files converted, {failed} failed and {skipped} skipped. They are still loaded.", report.succeeded),
+                    ),
+                };
+                app.notification().builder().title(title).body(body).show().unwrap();
+
+                Ok(RenameResponse { files, report: Some(report) })
             }
             MessageDialogResult::Cancel => Err(existing_response),
             _ => Err(existing_response),
@@ -315,6 +277,33 @@ pub async fn user_rename_files(
     }
 }
 
+/// Sends the progress of a batch or an undo to the window as "execute-progress" events, at most every PROGRESS_INTERVAL.
+pub fn progress_emitter(app: &tauri::AppHandle) -> impl FnMut(ExecutionProgress) + '_ {
+    let mut last_emit: Option<Instant> = None;
+    move |progress: ExecutionProgress| {
+        let finished = progress.files_done == progress.files_total;
+        if finished || last_emit.is_none_or(|last_emit| last_emit.elapsed() >= PROGRESS_INTERVAL) {
+            last_emit = Some(Instant::now());
+            let _ = app.emit("execute-progress", progress);
+        }
+    }
+}
+
+/// Stops the running batch before its next file. The files written so far stay renamed and are recorded for undo.
+#[tauri::command]
+pub fn user_cancel_batch(control: State<'_, BatchControl>) {
+    control.cancel.store(true, Ordering::SeqCst);
+}
+
+// NOTE: a failed journal write shouldn't fail the rename itself, the files are already on disk
+fn record_journal(app: &tauri::AppHandle, batch: JournalBatch) {
+    let recorded = journal_path(app).and_then(|path| record_batch(&path, batch));
+
+    if let Err(t) = recorded {
+        eprintln!("error: could not record batch in the undo journal: {t}");
+    }
+}
+
 // NOTE: This notification works on Arch Linux, and Win 11
 // TODO: Confirm that this works on MacOS.
 // TODO: If all platforms work, we need to properly do error handling.
@@ -0,0 +1,117 @@
+use crate::pipeline;
+use crate::template::{read_template, validate_template_name, write_template, Template, TEMPLATE_VERSION};
+use crate::{AppState, FileStatusResponse, Mutex, Output, Path, PathBuf, State};
+
+use std::fs;
+use tauri::Manager;
+
+const TEMPLATE_DIRECTORY: &str = "templates";
+const TEMPLATE_EXTENSION: &str = "json";
+
+/// The template that was loaded, plus the files with its tasks run on them.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LoadedTemplate {
+    pub template: Template,
+    pub files: FileStatusResponse,
+}
+
+fn templates_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let dir = app.path().app_config_dir().map_err(|e| e.to_string())?;
+    Ok(dir.join(TEMPLATE_DIRECTORY))
+}
+
+fn template_path(app: &tauri::AppHandle, name: &str) -> Result<PathBuf, String> {
+    let name = validate_template_name(name)?;
+    Ok(templates_dir(app)?.join(format!("{name}.{TEMPLATE_EXTENSION}")))
+}
+
+#[tauri::command]
+pub fn user_save_template(
+    name: String,
+    output_dropdown_choice: &str,
+    output_directory: &str,
+    state: State<'_, Mutex<AppState>>,
+    app: tauri::AppHandle,
+) -> Result<Template, String> {
+    let name = validate_template_name(&name)?;
+    let template = {
+        let mut state = state.lock().unwrap();
+        state.output = Output::from_choice(output_dropdown_choice, output_directory);
+        Template { version: TEMPLATE_VERSION, name: name.clone(), tasks: state.tasks.clone(), output: state.output.clone() }
+    };
+
+    write_template(&template_path(&app, &name)?, &template)?;
+    Ok(template)
+}
+
+#[tauri::command]
+pub fn user_list_templates(app: tauri::AppHandle) -> Result<Vec<String>, String> {
+    let dir = templates_dir(&app)?;
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == TEMPLATE_EXTENSION))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+        .collect();
+
+    names.sort_by(|a, b| natord::compare(&a.to_lowercase(), &b.to_lowercase()));
+    Ok(names)
+}
+
+/// Loads the template into AppState and reruns the tasks. The frontend rebuilds its task list from the returned template.
+#[tauri::command]
+pub fn user_load_template(name: String, state: State<'_, Mutex<AppState>>, app: tauri::AppHandle) -> Result<LoadedTemplate, String> {
+    let template = read_template(&template_path(&app, &name)?)?;
+
+    let mut state = state.lock().unwrap();
+    state.output = template.output.clone();
+    let files = pipeline::update_tasks(&mut state, template.tasks.clone());
+
+    Ok(LoadedTemplate { template, files })
+}
+
+#[tauri::command]
+pub fn user_delete_template(name: String, app: tauri::AppHandle) -> Result<(), String> {
+    fs::remove_file(template_path(&app, &name)?).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn user_rename_template(old_name: String, new_name: String, app: tauri::AppHandle) -> Result<Template, String> {
+    let old_path = template_path(&app, &old_name)?;
+    let new_path = template_path(&app, &new_name)?;
+
+    if new_path.exists() {
+        return Err(format!("A template named \"{}\" already exists", new_name.trim()));
+    }
+
+    let mut template = read_template(&old_path)?;
+    template.name = validate_template_name(&new_name)?;
+
+    write_template(&new_path, &template)?;
+    fs::remove_file(old_path).map_err(|e| e.to_string())?;
+    Ok(template)
+}
+
+#[tauri::command]
+pub fn user_import_template(path: String, app: tauri::AppHandle) -> Result<Template, String> {
+    let template = read_template(Path::new(&path))?;
+    let destination = template_path(&app, &template.name)?;
+
+    if destination.exists() {
+        return Err(format!("A template named \"{}\" already exists", template.name));
+    }
+
+    write_template(&destination, &template)?;
+    Ok(template)
+}
+
+#[tauri::command]
+pub fn user_export_template(name: String, path: String, app: tauri::AppHandle) -> Result<(), String> {
+    let template = read_template(&template_path(&app, &name)?)?;
+    write_template(Path::new(&path), &template)
+}
@@ -1,42 +1,150 @@
 //  <-- === WorkingFile Interface === -->
+export type Conflict = "None" | "ExistsOnDisk" | "CollidesWithSource" | "InvalidName" | "InsideMovingFolder";
+
+export type ConflictPolicy = "Skip" | "AutoSuffix" | "Overwrite" | "Abort";
+
+export type MoveVerification = "Size" | "Checksum";
+
 export interface FileStatus {
     old_file_name: string;
     new_file_name: string;
+    is_dir: boolean;
     active: boolean;
     selected: boolean;
     stable_id: number;
+    conflict: Conflict;
+    duplicate_group: number | null;
+}
+
+export type Symlinks = "Keep" | "Follow" | "Skip";
+
+export interface FolderOptions {
+    max_depth: number | null;
+    skip_hidden: boolean;
+    symlinks: Symlinks;
+    use_ignore_files: boolean;
+}
+
+export interface SortOptions {
+    natural: boolean;
+    case_insensitive: boolean;
+    then_by: string[];
 }
 
 export interface FileStatusStats {
     total: number;
+    visible: number;
     selected: number;
     filtered: number;
     ready: number;
+    conflicts: number;
+}
+
+export interface TaskError {
+    task_index: number;
+    message: string;
 }
 
+//  <-- === Only the rows of the window the table scrolled to, offset is the visible index of the first one === -->
 export interface FileStatusResponse {
+    offset: number;
     statuses: FileStatus[];
     stats: FileStatusStats;
+    task_errors: TaskError[];
+}
+
+//  <-- === Selection commands only send the stable_ids whose selection changed === -->
+export interface SelectionDelta {
+    selected: number[];
+    deselected: number[];
+    stats: FileStatusStats;
+}
+
+//  <-- === Execution Report Interfaces === -->
+export type FileOutcome = "Ok" | { Skipped: { reason: string } } | { Failed: { kind: string; message: string } };
+
+export interface FileReport {
+    source: string;
+    target: string;
+    outcome: FileOutcome;
+}
+
+export interface ExecutionReport {
+    cancelled: boolean;
+    succeeded: number;
+    skipped: number;
+    failed: number;
+    files: FileReport[];
+}
+
+export interface ExecutionProgress {
+    file: string;
+    files_done: number;
+    files_total: number;
+    files_failed: number;
+    bytes_done: number;
+    bytes_total: number;
+}
+
+export interface RenameResponse {
+    files: FileStatusResponse;
+    report: ExecutionReport | null;
+}
+
+//  <-- === Undo Journal Interfaces === -->
+export interface UndoSkipped {
+    source: string;
+    target: string;
+    reason: string;
+}
+
+export interface UndoReport {
+    restored: number;
+    skipped: UndoSkipped[];
+}
+
+//  <-- === Saved Template. Mirrors the Rust Template struct === -->
+export type Output = "Replace" | { Copy: { directory: string } } | { Move: { directory: string } };
+
+export interface Template {
+    version: number;
+    name: string;
+    tasks: Task[];
+    output: Output;
+}
+
+export interface LoadedTemplate {
+    template: Template;
+    files: FileStatusResponse;
 }
 
 //  <-- === Task Type. Contains all Sub Tasks within === -->
 export type Task =
-    | { CustomText: { text: string; at_start: boolean; active: boolean } }
+    | { CustomText: { text: string; at_start: boolean; condition?: Task | null; active: boolean } }
     | {
           FindAndReplace: {
               find_text: string;
               replace_text: string;
+              use_regex: boolean;
+              case_insensitive: boolean;
+              first_match_only: boolean;
+              condition?: Task | null;
               active: boolean;
           };
       }
-    | { ClearAll: { active: boolean } }
-    | { ChangeCase: { case_choice: number; active: boolean } }
+    | { ClearAll: { condition?: Task | null; active: boolean } }
+    | { ChangeCase: { case_choice: number; condition?: Task | null; active: boolean } }
     | {
           NumSequence: {
               start_num: number;
               num_padding: number;
               at_start: boolean;
               separator: string;
+              step: number;
+              numerals: Numerals;
+              active_only: boolean;
+              per_folder: boolean;
+              condition?: Task | null;
               active: boolean;
           };
       }
@@ -47,6 +155,8 @@ export type Task =
               day: boolean;
               separator: string;
               at_start: boolean;
+              date_sources: DateSource[];
+              condition?: Task | null;
               active: boolean;
           };
       }
@@ -54,14 +164,56 @@ export type Task =
           Time: {
               at_start: boolean;
               separator: string;
+              date_sources: DateSource[];
+              condition?: Task | null;
+              active: boolean;
+          };
+      }
+    | {
+          AudioTag: {
+              field: AudioTagField;
+              at_start: boolean;
+              separator: string;
+              padding: number;
+              fallback: string;
+              condition?: Task | null;
               active: boolean;
           };
       }
-    | { FilterName: { inclusive: boolean; name: string } }
-    | { FilterDocType: { inclusive: boolean; doc_types: string[] } }
+    | { Template: { template: string; pattern: string; start_num: number; condition?: Task | null; active: boolean } }
+    | {
+          FilterName: {
+              inclusive: boolean;
+              name: string;
+              name_target: NameTarget;
+              match_mode: MatchMode;
+              case_insensitive: boolean;
+              match_path: boolean;
+          };
+      }
+    | { FilterDocType: { inclusive: boolean; doc_types: string[]; name_target: NameTarget } }
     | { FilterTimePeriod: { inclusive: boolean; start_time: Date | null; end_time: Date | null } }
     | { FilterTime: { before: boolean; time: Date | null } }
-    | { FilterSize: { greater_than: boolean; byte_base_size: number; size: number } };
+    | { FilterSize: { greater_than: boolean; byte_base_size: number; size: number } }
+    | { FilterKind: { kind: EntryKind } }
+    | { FilterGroup: { operator: GroupOperator; filters: Task[] } }
+    | { FilterDuplicates: { keep: DuplicateKeep } };
+
+export type Numerals = "Decimal" | "LowercaseLetters" | "UppercaseLetters" | "LowercaseRoman" | "UppercaseRoman";
+
+export type GroupOperator = "All" | "Any" | "None";
+
+export type MatchMode = "Substring" | "Exact" | "Glob" | "Regex";
+
+export type NameTarget = "Original" | "Current";
+
+export type EntryKind = "Both" | "Files" | "Directories";
+
+export type DuplicateKeep = "First" | "Unique" | "Copies";
+
+export type DateSource = "Modified" | "Created" | "Accessed" | "ExifCaptured";
+
+export type AudioTagField = "Artist" | "AlbumArtist" | "Album" | "Title" | "Track" | "Disc" | "Year" | "Genre";
 
 //  <-- === Add unique ID to each task. Needed for proper animation in the DOM === -->
 export interface TaskWithId {
@@ -75,7 +227,14 @@ export const createCustomTextTask = (): Task => ({
 });
 
 export const createFindReplaceTask = (): Task => ({
-    FindAndReplace: { find_text: "", replace_text: "", active: true },
+    FindAndReplace: {
+        find_text: "",
+        replace_text: "",
+        use_regex: false,
+        case_insensitive: false,
+        first_match_only: false,
+        active: true,
+    },
 });
 
 export const createClearAllTask = (): Task => ({
@@ -87,23 +246,48 @@ export const createChangeCaseTask = (): Task => ({
 });
 
 export const createNumSequenceTask = (): Task => ({
-    NumSequence: { start_num: 0, num_padding: 4, at_start: true, separator: "_", active: true },
+    NumSequence: {
+        start_num: 0,
+        num_padding: 4,
+        at_start: true,
+        separator: "_",
+        step: 1,
+        numerals: "Decimal",
+        active_only: false,
+        per_folder: false,
+        active: true,
+    },
 });
 
 export const createDateTask = (): Task => ({
-    Date: { year: 0, month: true, day: true, at_start: true, separator: "_", active: true },
+    Date: { year: 0, month: true, day: true, at_start: true, separator: "_", date_sources: ["Modified"], active: true },
 });
 
 export const createTimeTask = (): Task => ({
-    Time: { at_start: true, separator: "_", active: true },
+    Time: { at_start: true, separator: "_", date_sources: ["Modified"], active: true },
+});
+
+export const createAudioTagTask = (): Task => ({
+    AudioTag: { field: "Artist", at_start: true, separator: " - ", padding: 2, fallback: "", active: true },
+});
+
+export const createTemplateTask = (): Task => ({
+    Template: { template: "{stem}_{n:03}{ext}", pattern: "", start_num: 1, active: true },
 });
 
 export const createFilterNameTask = (): Task => ({
-    FilterName: { inclusive: true, name: "" },
+    FilterName: {
+        inclusive: true,
+        name: "",
+        name_target: "Original",
+        match_mode: "Substring",
+        case_insensitive: false,
+        match_path: false,
+    },
 });
 
 export const createFilterDocTypeTask = (): Task => ({
-    FilterDocType: { inclusive: true, doc_types: [] },
+    FilterDocType: { inclusive: true, doc_types: [], name_target: "Original" },
 });
 
 export const createFilterTimePeriodTask = (): Task => ({
@@ -118,7 +302,24 @@ export const createFilterSizeTask = (): Task => ({
     FilterSize: { greater_than: false, byte_base_size: 2, size: 0 },
 });
 
+export const createFilterKindTask = (): Task => ({
+    FilterKind: { kind: "Files" },
+});
+
+export const createFilterGroupTask = (): Task => ({
+    FilterGroup: { operator: "All", filters: [] },
+});
+
+export const createFilterDuplicatesTask = (): Task => ({
+    FilterDuplicates: { keep: "First" },
+});
+
 //  <-- === Type Guards === -->
+// filters narrow down the batch, every other task is a transform that can have a condition
+export const isFilter = (task: Task): boolean => {
+    return Object.keys(task)[0].startsWith("Filter");
+};
+
 export const isCustomText = (
     task: Task,
 ): task is {
@@ -133,6 +334,9 @@ export const isFindAndReplace = (
     FindAndReplace: {
         find_text: string;
         replace_text: string;
+        use_regex: boolean;
+        case_insensitive: boolean;
+        first_match_only: boolean;
         active: boolean;
     };
 } => {
@@ -163,6 +367,10 @@ export const isNumSequence = (
         num_padding: number;
         at_start: boolean;
         separator: string;
+        step: number;
+        numerals: Numerals;
+        active_only: boolean;
+        per_folder: boolean;
         active: boolean;
     };
 } => {
@@ -178,6 +386,7 @@ export const isDate = (
         day: boolean;
         at_start: boolean;
         separator: string;
+        date_sources: DateSource[];
         active: boolean;
     };
 } => {
@@ -191,18 +400,46 @@ export const isTime = (
         at_start: boolean;
         ampm: boolean;
         separator: string;
+        date_sources: DateSource[];
         active: boolean;
     };
 } => {
     return "Time" in task;
 };
 
+export const isAudioTag = (
+    task: Task,
+): task is {
+    AudioTag: {
+        field: AudioTagField;
+        at_start: boolean;
+        separator: string;
+        padding: number;
+        fallback: string;
+        active: boolean;
+    };
+} => {
+    return "AudioTag" in task;
+};
+
+export const isTemplate = (
+    task: Task,
+): task is {
+    Template: { template: string; pattern: string; start_num: number; active: boolean };
+} => {
+    return "Template" in task;
+};
+
 export const isFilterName = (
     task: Task,
 ): task is {
     FilterName: {
         inclusive: boolean;
         name: string;
+        name_target: NameTarget;
+        match_mode: MatchMode;
+        case_insensitive: boolean;
+        match_path: boolean;
     };
 } => {
     return "FilterName" in task;
@@ -214,6 +451,7 @@ export const isFilterDocType = (
     FilterDocType: {
         inclusive: boolean;
         doc_types: string[];
+        name_target: NameTarget;
     };
 } => {
     return "FilterDocType" in task;
@@ -253,3 +491,34 @@ export const isFilterSize = (
 } => {
     return "FilterSize" in task;
 };
+
+export const isFilterKind = (
+    task: Task,
+): task is {
+    FilterKind: {
+        kind: EntryKind;
+    };
+} => {
+    return "FilterKind" in task;
+};
+
+export const isFilterGroup = (
+    task: Task,
+): task is {
+    FilterGroup: {
+        operator: GroupOperator;
+        filters: Task[];
+    };
+} => {
+    return "FilterGroup" in task;
+};
+
+export const isFilterDuplicates = (
+    task: Task,
+): task is {
+    FilterDuplicates: {
+        keep: DuplicateKeep;
+    };
+} => {
+    return "FilterDuplicates" in task;
+};
@@ -0,0 +1,18 @@
+import type { DateSource } from "../types";
+
+//  <-- === Date source presets for the Date and Time tasks. Sources are tried in order === -->
+export const dateSourcePresets: { label: string; value: string; sources: DateSource[] }[] = [
+    { label: "Modified", value: "modified", sources: ["Modified"] },
+    { label: "Created", value: "created", sources: ["Created", "Modified"] },
+    { label: "Accessed", value: "accessed", sources: ["Accessed", "Modified"] },
+    { label: "Photo Taken", value: "exif", sources: ["ExifCaptured", "Created", "Modified"] },
+];
+
+export function presetOf(sources: DateSource[] | undefined): string {
+    const preset = dateSourcePresets.find((preset) => preset.sources.join() === (sources ?? []).join());
+    return preset?.value ?? "modified";
+}
+
+export function sourcesOf(value: string): DateSource[] {
+    return dateSourcePresets.find((preset) => preset.value === value)?.sources ?? ["Modified"];
+}
@@ -0,0 +1,71 @@
+import type { Task } from "../types";
+import {
+    createFilterDocTypeTask,
+    createFilterDuplicatesTask,
+    createFilterGroupTask,
+    createFilterKindTask,
+    createFilterNameTask,
+    createFilterSizeTask,
+    createFilterTimePeriodTask,
+    createFilterTimeTask,
+    isFilterDocType,
+    isFilterName,
+    isFilterTimePeriod,
+} from "../types";
+
+//  <-- === Filters inside conditions and groups. They say which files they keep, so name and type filters keep matches === -->
+export const filterKindOptions: { label: string; value: string }[] = [
+    { label: "Name", value: "FilterName" },
+    { label: "File type", value: "FilterDocType" },
+    { label: "Size", value: "FilterSize" },
+    { label: "Modified", value: "FilterTime" },
+    { label: "Modified in period", value: "FilterTimePeriod" },
+    { label: "File or folder", value: "FilterKind" },
+    { label: "Duplicate", value: "FilterDuplicates" },
+    { label: "Group", value: "FilterGroup" },
+];
+
+export const matchModeOptions: { label: string; value: string }[] = [
+    { label: "Contains", value: "Substring" },
+    { label: "Is exactly", value: "Exact" },
+    { label: "Glob", value: "Glob" },
+    { label: "Regex", value: "Regex" },
+];
+
+export const duplicateKeepOptions: { label: string; value: string }[] = [
+    { label: "First copy and unique files", value: "First" },
+    { label: "Only files without copies", value: "Unique" },
+    { label: "Only the extra copies", value: "Copies" },
+];
+
+export function filterKindOf(filter: Task): string {
+    return Object.keys(filter)[0];
+}
+
+export function createFilterOfKind(kind: string): Task {
+    const filter = (() => {
+        switch (kind) {
+            case "FilterName":
+                return createFilterNameTask();
+            case "FilterDocType":
+                return createFilterDocTypeTask();
+            case "FilterSize":
+                return createFilterSizeTask();
+            case "FilterTime":
+                return createFilterTimeTask();
+            case "FilterTimePeriod":
+                return createFilterTimePeriodTask();
+            case "FilterKind":
+                return createFilterKindTask();
+            case "FilterDuplicates":
+                return createFilterDuplicatesTask();
+            default:
+                return createFilterGroupTask();
+        }
+    })();
+
+    if (isFilterName(filter)) filter.FilterName.inclusive = false;
+    if (isFilterDocType(filter)) filter.FilterDocType.inclusive = false;
+    if (isFilterTimePeriod(filter)) filter.FilterTimePeriod.inclusive = false;
+    return filter;
+}